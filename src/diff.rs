@@ -0,0 +1,209 @@
+/// Lines of unchanged context shown around each change, same as `diff -u`
+const CONTEXT: usize = 3;
+
+#[derive(Debug, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Past this many changed lines a file is only reported as changed, since
+/// the edit script search grows with the square of the edit distance
+const MAX_EDITS: isize = 1000;
+
+/// Computes the shortest edit script turning `old` into `new` using Myers'
+/// algorithm, returned in order, or `None` if it takes more than
+/// [`MAX_EDITS`] edits
+fn diff_lines(old: &[&str], new: &[&str]) -> Option<Vec<Edit>> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    // Each step only keeps the diagonals it can reach, row d covering k from
+    // -(d + 1) to d + 1
+    let mut trace = Vec::new();
+    let at = |row: &[isize], d: isize, k: isize| row[(k + d + 1) as usize];
+
+    'search: for d in 0..=max {
+        if d > MAX_EDITS {
+            return None;
+        }
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, row) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(row, d, k - 1) < at(row, d, k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(row, d, prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+
+        if d > 0 {
+            edits.push(if x == prev_x {
+                Edit::Insert
+            } else {
+                Edit::Delete
+            });
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    Some(edits)
+}
+
+/// Renders a unified diff between two texts, or `None` if they are identical
+/// or differ in too many lines to be worth showing
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> Option<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = diff_lines(&old_lines, &new_lines)?;
+
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| **edit != Edit::Equal)
+        .map(|(i, _)| i)
+        .collect();
+
+    if changes.is_empty() {
+        return None;
+    }
+
+    // Position in both texts right before each edit
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for edit in &edits {
+        positions.push((old_pos, new_pos));
+        match edit {
+            Edit::Equal => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            Edit::Delete => old_pos += 1,
+            Edit::Insert => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    // Changes close enough that their context overlaps share a hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for change in changes {
+        let start = change.saturating_sub(CONTEXT);
+        let end = (change + CONTEXT + 1).min(edits.len());
+
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {old_label}\n+++ {new_label}\n");
+
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let old_count = old_end - old_start;
+        let new_count = new_end - new_start;
+
+        out += &format!(
+            "@@ -{},{old_count} +{},{new_count} @@\n",
+            if old_count == 0 {
+                old_start
+            } else {
+                old_start + 1
+            },
+            if new_count == 0 {
+                new_start
+            } else {
+                new_start + 1
+            },
+        );
+
+        for (edit, (old_pos, new_pos)) in edits[start..end].iter().zip(&positions[start..end]) {
+            match edit {
+                Edit::Equal => out += &format!(" {}\n", old_lines[*old_pos]),
+                Edit::Delete => out += &format!("-{}\n", old_lines[*old_pos]),
+                Edit::Insert => out += &format!("+{}\n", new_lines[*new_pos]),
+            }
+        }
+    }
+
+    Some(out)
+}
+
+/// Treats contents as text when they are valid UTF-8 without NUL bytes
+pub fn as_text(contents: &[u8]) -> Option<&str> {
+    if contents.contains(&0) {
+        return None;
+    }
+    std::str::from_utf8(contents).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\nh\ni\nj\nk\n";
+
+        assert_eq!(
+            unified_diff(old, new, "old", "new").unwrap(),
+            "--- old\n+++ new\n@@ -2,9 +2,10 @@\n b\n c\n d\n-e\n+E\n f\n g\n h\n i\n j\n+k\n"
+        );
+        assert_eq!(unified_diff(old, old, "old", "new"), None);
+        assert_eq!(
+            unified_diff("", "a\n", "old", "new").unwrap(),
+            "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+a\n"
+        );
+
+        let rewritten: String = (0..4000).map(|i| format!("{i}\n")).collect();
+        let changed: String = (0..4000).map(|i| format!("{i}x\n")).collect();
+        assert_eq!(unified_diff(&rewritten, &changed, "old", "new"), None);
+    }
+
+    #[test]
+    fn test_as_text() {
+        assert_eq!(as_text(b"general {\n}\n"), Some("general {\n}\n"));
+        assert_eq!(as_text(&[0x89, 0x50, 0x4e, 0x47, 0x00]), None);
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running hook is checked on while waiting for it
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub enum HookStatus {
+    Success,
+    /// Describes how the hook failed, e.g. its exit code
+    Error(String),
+    Nothing,
+}
+
+/// Everything needed to run a single hook command
+pub struct HookCommand<'a> {
+    pub shell: &'a str,
+    pub command: &'a str,
+    pub cwd: Option<&'a Path>,
+    pub env: Vec<(&'a str, String)>,
+    pub stdin: File,
+    /// Where both stdout and stderr go, discarded when unset. A file rather
+    /// than a pipe, so anything the hook leaves running in the background
+    /// can keep writing without holding up snapsr.
+    pub output: Option<File>,
+    /// Kill the hook when it runs longer than this
+    pub timeout: Option<Duration>,
+}
+
+/// Kills the hook along with anything it started, since a shell that times
+/// out usually is just waiting on one of its children
+fn kill_group(child: &mut Child) {
+    // The hook leads its own process group, so its pid is the group's id
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> io::Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return child.wait().map(Some);
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill_group(child);
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn describe_exit(status: ExitStatus) -> HookStatus {
+    if status.success() {
+        HookStatus::Success
+    } else if let Some(code) = status.code() {
+        HookStatus::Error(format!("exited with code {code}"))
+    } else if let Some(signal) = status.signal() {
+        HookStatus::Error(format!("was killed by signal {signal}"))
+    } else {
+        HookStatus::Error(String::from("failed for an unknown reason"))
+    }
+}
+
+/// Runs `<shell> -c <command>` in its own process group, sending its output
+/// to the hook's output file
+pub fn run(hook: HookCommand) -> HookStatus {
+    if hook.command.trim().is_empty() {
+        return HookStatus::Nothing;
+    }
+
+    let (stdout, stderr) = match hook.output {
+        Some(output) => match output.try_clone() {
+            Ok(stderr) => (Stdio::from(output), Stdio::from(stderr)),
+            Err(err) => return HookStatus::Error(format!("couldn't be given its output ({err})")),
+        },
+        None => (Stdio::null(), Stdio::null()),
+    };
+
+    let mut command = Command::new(hook.shell);
+    command
+        .arg("-c")
+        .arg(hook.command)
+        .envs(hook.env.iter().map(|(key, value)| (key, value)))
+        .stdin(hook.stdin)
+        .stdout(stdout)
+        .stderr(stderr)
+        .process_group(0);
+
+    if let Some(cwd) = hook.cwd {
+        command.current_dir(cwd);
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => return HookStatus::Error(format!("couldn't be started ({err})")),
+    };
+
+    match wait_with_timeout(&mut child, hook.timeout) {
+        Ok(Some(status)) => describe_exit(status),
+        Ok(None) => HookStatus::Error(format!(
+            "timed out after {}s and was killed",
+            hook.timeout.unwrap_or_default().as_secs()
+        )),
+        Err(err) => HookStatus::Error(format!("couldn't be waited on ({err})")),
+    }
+}
+
+/// Parses a hook with `<shell> -n` without running any of it, returning the
+/// shell's complaint when it doesn't parse
+pub fn check_syntax(shell: &str, command: &str) -> Result<(), String> {
+    let output = Command::new(shell)
+        .arg("-n")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("{shell} couldn't be started ({err})"))?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(stderr
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("syntax error")
+        .trim()
+        .to_string())
+}
@@ -30,6 +30,72 @@ struct Cli {
 
     #[arg(long, value_name = "POST_HOOK", help = "Post hook when snapping")]
     post: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PARENT_SNAP",
+        help = "Only saves what changed since the given Snap when snapping"
+    )]
+    parent: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Treats the given directory as / when snapping or restoring"
+    )]
+    root: Option<PathBuf>,
+
+    #[arg(short, long, help = "Shows text diffs of changed files when diffing")]
+    patch: bool,
+
+    #[arg(
+        long,
+        help = "Prints what a restore would do without changing anything"
+    )]
+    dry_run: bool,
+
+    #[arg(long, requires = "dry_run", help = "Prints the dry run as JSON")]
+    json: bool,
+
+    #[arg(
+        short,
+        long = "module",
+        value_name = "MODULE",
+        help = "Only restores the given module, can be repeated. Picks the module whose hook to set, clear or test"
+    )]
+    modules: Vec<String>,
+
+    #[arg(
+        long = "path",
+        value_name = "PATH",
+        help = "Only restores the given path or glob out of a Snap, can be repeated"
+    )]
+    paths: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Runs the Snap's hooks even when only part of it is restored"
+    )]
+    with_hooks: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "with_hooks",
+        help = "Doesn't run any of the Snap's hooks when restoring or undoing"
+    )]
+    no_hooks: bool,
+
+    #[arg(
+        long,
+        help = "Lets a restore replace directories that aren't empty with files, losing their contents"
+    )]
+    force: bool,
+
+    #[arg(long, help = "Don't restore file ownership when restoring")]
+    no_owner: bool,
+
+    #[arg(long, help = "Don't restore modification times when restoring")]
+    no_times: bool,
 }
 
 #[derive(Args)]
@@ -41,6 +107,9 @@ struct Arg {
     #[arg(short, long, value_name = "SNAP_NAME", help = "Restores a Snap")]
     restore: Option<String>,
 
+    #[arg(long, help = "Undoes the most recent restore")]
+    undo: bool,
+
     #[arg(short, long, value_name = "SNAP_NAME", help = "Deletes a Snap")]
     delete: Option<String>,
 
@@ -53,6 +122,67 @@ struct Arg {
     #[arg(long, help="Rename a snap, ex. 'old_name:new_name'", value_parser = parse_rename_args)]
     rename: Option<(String, String)>,
 
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["SNAP_NAME", "FILE"],
+        help = "Exports a Snap to a single archive file"
+    )]
+    export: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        num_args = 1..=2,
+        value_names = ["FILE", "SNAP_NAME"],
+        help = "Imports a Snap from an archive file, optionally under a new name"
+    )]
+    import: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        num_args = 1..=2,
+        value_names = ["SNAP_NAME", "OTHER_SNAP"],
+        help = "Shows what differs between a Snap and the files on disk, or another Snap"
+    )]
+    diff: Option<Vec<String>>,
+
+    #[arg(long, value_name = "SNAP_NAME", help = "Shows the hooks of a Snap")]
+    show_hooks: Option<String>,
+
+    #[arg(
+        long,
+        num_args = 3,
+        value_names = ["SNAP_NAME", "STAGE", "COMMAND"],
+        help = "Sets the pre_load or post_load hook of a Snap"
+    )]
+    set_hook: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["SNAP_NAME", "STAGE"],
+        help = "Clears the pre_load or post_load hook of a Snap"
+    )]
+    clear_hook: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["SNAP_NAME", "STAGE"],
+        help = "Runs the pre_load or post_load hooks of a Snap without restoring it"
+    )]
+    test_hook: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        value_name = "SNAP_NAME",
+        help = "Replaces the hooks of a Snap with the ones in the config"
+    )]
+    refresh_hooks: Option<String>,
+
+    #[arg(long, help = "Checks the Snap config for mistakes")]
+    check: bool,
+
     #[arg(long, help = "Setups environment for Snapsr")]
     setup: bool,
 }
@@ -87,6 +217,11 @@ fn setup_env() {
         });
     }
 
+    store::create_objects_dir().unwrap_or_else(|err| {
+        println!("[\x1b[1;91m-\x1b[0m] Failed to create objects directory ({err})");
+        process::exit(1);
+    });
+
     let snap_config_path = base_snaps_dir.join("config.toml");
     if !snap_config_path.exists() {
         File::create(snap_config_path).unwrap_or_else(|err| {
@@ -102,31 +237,121 @@ fn setup_env() {
             process::exit(1);
         });
 
-        log_file.write(b"{}").unwrap_or_else(|err| {
+        log_file.write_all(b"{}").unwrap_or_else(|err| {
             println!("[\x1b[1;91m-\x1b[0m] Failed to initialize snap log file ({err})");
             process::exit(1);
         });
     }
+
+    snaps::migrate_legacy_snaps();
+}
+
+/// The module a hook command applies to, if any. Only one can be picked.
+fn hook_module(modules: &[String]) -> Option<&str> {
+    if modules.len() > 1 {
+        logger::log(
+            logger::LogLevel::Error,
+            "Hooks can only be changed for one module at a time",
+        );
+        process::exit(1);
+    }
+    modules.first().map(String::as_str)
 }
 
+mod diff;
+mod hook;
 mod logger;
 mod snaps;
+mod store;
 
 fn main() {
     let cli = Cli::parse();
 
     if let Some(snap) = cli.args.snap {
         setup_env();
-        snaps::cmd_snap(snap, cli.file, cli.pre, cli.post, cli.verbose);
+        let root = cli
+            .root
+            .map(|root| std::path::absolute(&root).unwrap_or(root));
+        snaps::cmd_snap(
+            snap,
+            cli.file,
+            cli.pre,
+            cli.post,
+            cli.parent,
+            root,
+            cli.verbose,
+        );
     } else if let Some(snap) = cli.args.restore {
         setup_env();
-        snaps::cmd_restore_snap(snap, cli.verbose);
+        let options = snaps::RestoreOptions {
+            owner: !cli.no_owner,
+            times: !cli.no_times,
+            dry_run: cli.dry_run,
+            json: cli.json,
+            modules: cli.modules,
+            paths: cli.paths,
+            with_hooks: cli.with_hooks,
+            no_hooks: cli.no_hooks,
+            force: cli.force,
+            root: cli
+                .root
+                .map(|root| std::path::absolute(&root).unwrap_or(root)),
+        };
+        snaps::cmd_restore_snap(snap, options, cli.verbose);
+    } else if cli.args.undo {
+        setup_env();
+        let options = snaps::RestoreOptions {
+            owner: !cli.no_owner,
+            times: !cli.no_times,
+            dry_run: false,
+            json: false,
+            modules: Vec::new(),
+            paths: Vec::new(),
+            with_hooks: false,
+            no_hooks: cli.no_hooks,
+            force: cli.force,
+            root: None,
+        };
+        snaps::cmd_undo_restore(options, cli.verbose);
     } else if let Some(snap) = cli.args.delete {
         setup_env();
-        snaps::cmd_delete_snap(snap);
+        snaps::cmd_delete_snap(snap, cli.file, cli.verbose);
     } else if let Some((old_name, new_name)) = cli.args.rename {
         setup_env();
-        snaps::cmd_rename_snap(old_name.as_str(), new_name.as_str());
+        snaps::cmd_rename_snap(old_name.as_str(), new_name.as_str(), cli.file, cli.verbose);
+    } else if let Some(args) = cli.args.export {
+        setup_env();
+        snaps::cmd_export_snap(&args[0], &PathBuf::from(&args[1]));
+    } else if let Some(args) = cli.args.import {
+        setup_env();
+        snaps::cmd_import_snap(&PathBuf::from(&args[0]), args.get(1).cloned());
+    } else if let Some(snaps) = cli.args.diff {
+        setup_env();
+        match snaps.as_slice() {
+            [snap, other_snap] => snaps::cmd_diff_snaps(snap, other_snap, cli.patch),
+            _ => snaps::cmd_diff_snap(&snaps[0], cli.file, cli.patch),
+        }
+    } else if let Some(snap) = cli.args.show_hooks {
+        setup_env();
+        snaps::cmd_show_hooks(&snap);
+    } else if let Some(args) = cli.args.set_hook {
+        setup_env();
+        let module = hook_module(&cli.modules);
+        snaps::cmd_set_hook(&args[0], &args[1], Some(args[2].clone()), module);
+    } else if let Some(args) = cli.args.clear_hook {
+        setup_env();
+        let module = hook_module(&cli.modules);
+        snaps::cmd_set_hook(&args[0], &args[1], None, module);
+    } else if let Some(args) = cli.args.test_hook {
+        setup_env();
+        let module = hook_module(&cli.modules);
+        snaps::cmd_test_hook(&args[0], &args[1], module);
+    } else if let Some(snap) = cli.args.refresh_hooks {
+        setup_env();
+        snaps::cmd_refresh_hooks(&snap, cli.file);
+    } else if cli.args.check {
+        setup_env();
+        snaps::cmd_check_config(cli.file);
     } else if cli.args.list {
         setup_env();
         snaps::cmd_list_snaps();
@@ -1,35 +1,60 @@
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::error::Error;
 use std::ffi::OsStr;
 use std::fs;
 use std::io;
+use std::io::Read;
+use std::io::Seek;
 use std::io::Write;
+use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::PermissionsExt;
 use std::path;
 use std::path::Component;
 use std::path::{Path, PathBuf};
 use std::process;
-use std::process::Command;
-use std::process::Stdio;
+use std::time::Duration;
 
 use chrono::prelude::*;
+use filetime::FileTime;
+use flate2::Compression as GzLevel;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use glob::glob;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::diff;
+use crate::hook;
+use crate::hook::HookStatus;
 use crate::logger;
 use crate::logger::LogLevel;
 use crate::logger::log;
+use crate::store;
+use crate::store::Compression;
 
-enum HookStatus {
-    Success,
-    Error,
-    Nothing,
-}
+/// How many checkpoints are kept around for `--undo`
+const CHECKPOINT_HISTORY: usize = 10;
+
+/// Seconds a hook may run before it gets killed, unless configured otherwise
+const DEFAULT_HOOK_TIMEOUT: u64 = 60;
+
+/// How many per-run hook logs are kept around
+const HOOK_LOG_HISTORY: usize = 20;
 
+#[derive(Clone, Copy)]
 enum HookType {
     Pre,
     Post,
+    PreSnap,
+    PostSnap,
+    PostDelete,
+    PostRename,
 }
 
 struct DisplayTable {
@@ -43,25 +68,230 @@ struct SnapConfig {
     #[serde(default)]
     modules: HashMap<String, ModuleConfig>,
     hooks: Option<Hooks>,
+    /// Default compression for modules that don't pick their own
+    compression: Option<Compression>,
+    /// Globs left out of every module
+    #[serde(default)]
+    exclude: Vec<String>,
+    /// Other config files whose modules and hooks are merged into this one
+    #[serde(default)]
+    include: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
 #[allow(dead_code)]
 struct ModuleConfig {
     include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
     description: Option<String>,
+    #[serde(default)]
+    symlinks: SymlinkMode,
+    compression: Option<Compression>,
+    /// Hooks run around restoring just this module
+    pre_load: Option<HookStage>,
+    post_load: Option<HookStage>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum SymlinkMode {
+    /// Record symlinks as symlinks along with their target
+    #[default]
+    Preserve,
+    /// Snap whatever the symlink points to as if it were at the link's path
+    Follow,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ItemKind {
+    File,
+    Dir,
+    Symlink(PathBuf),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct SnapItem {
+    module: String,
+    kind: ItemKind,
+    /// Hash of the item's contents in the object store, only set for files
+    object: Option<String>,
+    #[serde(default)]
+    compression: Compression,
+    /// Unset when they couldn't be read, in which case the restored item
+    /// keeps whatever attributes it gets when written
+    #[serde(default)]
+    attrs: Option<ItemAttrs>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct ItemAttrs {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: i64,
+    mtime_nsec: u32,
+    #[serde(default)]
+    xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+/// How a restore or undo is carried out: what gets restored, where, which
+/// of the recorded attributes are applied and whether hooks run
+pub struct RestoreOptions {
+    /// Restore ownership, only done when running as root since nobody else
+    /// can give files away
+    pub owner: bool,
+    pub times: bool,
+    /// Only print what the restore would do
+    pub dry_run: bool,
+    /// Print the dry run plan as JSON
+    pub json: bool,
+    /// Only restore the items of these modules, all of them when empty
+    pub modules: Vec<String>,
+    /// Only restore items matching these paths or globs, all of them when empty
+    pub paths: Vec<String>,
+    /// Run the snap's hooks even though only part of the snap is restored
+    pub with_hooks: bool,
+    /// Don't run any of the snap's hooks, nor ask to approve them
+    pub no_hooks: bool,
+    /// Replace directories that aren't empty with the snap's files
+    pub force: bool,
+    /// Directory the snap's paths are restored under instead of `/`
+    pub root: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct Hooks {
-    pre_load: Option<String>,
-    post_load: Option<String>,
+    pre_load: Option<HookStage>,
+    post_load: Option<HookStage>,
+    /// Run from the config around taking, deleting and renaming snaps
+    #[serde(default)]
+    pre_snap: Option<HookStage>,
+    #[serde(default)]
+    post_snap: Option<HookStage>,
+    #[serde(default)]
+    post_delete: Option<HookStage>,
+    #[serde(default)]
+    post_rename: Option<HookStage>,
+    /// Interpreter hooks are run with as `<shell> -c <hook>`, `sh` by default
+    #[serde(default)]
+    shell: Option<String>,
+    /// Directory hooks are run in, the current one by default
+    #[serde(default)]
+    cwd: Option<PathBuf>,
+    /// Seconds each hook may run before it's killed, 0 to never kill it
+    #[serde(default)]
+    timeout: Option<u64>,
+}
+
+/// The commands of one hook stage, run in order. Configs can give a single
+/// command or a list, each either a plain string or a table with its own
+/// `on_failure`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "HookStageRepr")]
+struct HookStage(Vec<HookEntry>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HookStageRepr {
+    One(HookEntry),
+    Many(Vec<HookEntry>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "HookEntryRepr")]
+struct HookEntry {
+    run: String,
+    on_failure: OnFailure,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HookEntryRepr {
+    Command(String),
+    Entry {
+        run: String,
+        #[serde(default)]
+        on_failure: OnFailure,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum OnFailure {
+    /// Stop right there, before the command the hook guards runs
+    Abort,
+    /// Report the failure and carry on
+    #[default]
+    Warn,
+    Ignore,
+}
+
+/// How a stage of hooks went as a whole
+#[derive(PartialEq)]
+enum HooksOutcome {
+    Success,
+    /// A hook set to `warn` failed
+    Warned,
+    /// A hook set to `abort` failed, nothing after it ran
+    Aborted,
+}
+
+/// What the hooks of a restore get told about it
+struct HookContext<'a> {
+    snap: &'a str,
+    modules: BTreeSet<String>,
+    /// Every path the restore writes, along with the module it belongs to
+    items: Vec<(&'a Path, &'a str)>,
+    /// Where the output of every hook run is saved
+    log_path: PathBuf,
+    /// Extra variables on top of the usual `SNAPSR_*` ones
+    env: Vec<(&'static str, String)>,
+}
+
+impl<'a> HookContext<'a> {
+    /// Context for hooks that don't run around restoring files
+    fn new(snap: &'a str, action: &str) -> Self {
+        Self {
+            snap,
+            modules: BTreeSet::new(),
+            items: Vec::new(),
+            log_path: new_hook_log(&format!("{action}-{snap}")),
+            env: Vec::new(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct SnapMetaData {
     timestamp: DateTime<Local>,
     size: u64,
+    /// Space the snap's files take up in the object store after compression
+    #[serde(default)]
+    stored_size: u64,
+    items: HashMap<PathBuf, SnapItem>,
+    hooks: Option<Hooks>,
+    /// Hooks of the modules that have any, run only when the module is restored
+    #[serde(default)]
+    module_hooks: BTreeMap<String, Hooks>,
+    /// Snap this one was taken on top of. `items` then only holds what was
+    /// added or changed since the parent and `removed` what no longer exists.
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    removed: BTreeSet<PathBuf>,
+    /// Only set on checkpoints, the snap whose restore the checkpoint undoes.
+    /// `removed` then holds the paths that didn't exist before the restore.
+    #[serde(default)]
+    restored: Option<String>,
+}
+
+/// Layout of snap.json from before the object store, where `items` maps each
+/// snapped path to its copy inside the snap's directory
+#[derive(Deserialize, Debug)]
+struct LegacySnapMetaData {
+    timestamp: DateTime<Local>,
     items: HashMap<PathBuf, PathBuf>,
     hooks: Option<Hooks>,
 }
@@ -70,6 +300,18 @@ struct SnapMetaData {
 struct SnapLog {
     #[serde(default)]
     snaps: HashMap<String, PathBuf>,
+    /// Set once every snap was moved into the object store, so older layouts
+    /// aren't looked for again
+    #[serde(default)]
+    migrated: bool,
+}
+
+/// Fingerprints of the hook commands allowed to run on this machine, either
+/// because a local snap was taken with them or because they were approved
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct TrustedHooks {
+    #[serde(default)]
+    fingerprints: BTreeSet<String>,
 }
 
 impl DisplayTable {
@@ -101,97 +343,480 @@ impl DisplayTable {
         println!("{}", sep);
 
         for row in &self.rows {
-            println!("{}", format_row(&row))
+            println!("{}", format_row(row))
         }
     }
 }
 
 impl SnapConfig {
-    fn from(path: PathBuf) -> Option<Self> {
-        match fs::read_to_string(path) {
-            Ok(txt) => {
-                let txt = Self::parse_for_template(txt);
-                match toml::from_str(&txt) {
-                    Ok(config) => Some(config),
-                    Err(_) => None,
-                }
+    /// Loads the config at `path`, or the default config when none is given
+    fn load(path: Option<PathBuf>) -> Result<Self, String> {
+        Self::read(&Self::resolve_path(path))
+    }
+
+    fn resolve_path(path: Option<PathBuf>) -> PathBuf {
+        path.unwrap_or_else(|| PathBuf::from(get_snap_config_dir()).join("config.toml"))
+    }
+
+    /// Reads a config along with every file it includes
+    fn read(path: &Path) -> Result<Self, String> {
+        let mut module_files = HashMap::new();
+        Self::read_file(path, &mut Vec::new(), &mut module_files)
+    }
+
+    /// Reads one config file and merges the files it includes into it.
+    /// `including` is the chain of files that led here, to catch includes
+    /// going in circles, and `module_files` remembers where each module was
+    /// defined to point at both files when one is defined twice.
+    fn read_file(
+        path: &Path,
+        including: &mut Vec<PathBuf>,
+        module_files: &mut HashMap<String, PathBuf>,
+    ) -> Result<Self, String> {
+        let canonical = fs::canonicalize(path)
+            .map_err(|err| format!("Failed to read {} ({err})", path.display()))?;
+
+        if let Some(start) = including.iter().position(|file| *file == canonical) {
+            let cycle = including[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|file| file.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(format!("Recursive include {cycle}"));
+        }
+
+        let txt = fs::read_to_string(&canonical)
+            .map_err(|err| format!("Failed to read {} ({err})", path.display()))?;
+
+        let mut config: Self = toml::from_str(&txt).map_err(|err| {
+            let position = err.span().map(|span| text_position(&txt, span.start));
+
+            // Only a template line the parser actually tripped over counts,
+            // the same text inside a multi-line string is fine
+            if let Some((line, _)) = position
+                && let Some(text) = txt.lines().nth(line - 1)
+                && text.trim_start().starts_with("template ")
+            {
+                let template = text.split_whitespace().nth(1).unwrap_or_default();
+                return format!(
+                    "{} line {line}: template lines are no longer supported, use include = [\"templates/{template}\"] instead",
+                    path.display()
+                );
+            }
+
+            let location = match position {
+                Some((line, column)) => format!("line {line}, column {column}"),
+                None => String::from("unknown location"),
+            };
+            format!(
+                "Invalid TOML in {} at {location}: {}",
+                path.display(),
+                err.message().trim().replace('\n', ", ")
+            )
+        })?;
+
+        for module_name in config.modules.keys() {
+            if let Some(first) = module_files.insert(module_name.clone(), path.to_path_buf()) {
+                return Err(format!(
+                    "Module {module_name} is defined in both {} and {}",
+                    first.display(),
+                    path.display()
+                ));
             }
+        }
 
-            Err(_) => None,
+        including.push(canonical);
+        let base = path.parent().unwrap_or(Path::new(""));
+        for pattern in std::mem::take(&mut config.include) {
+            for include_path in resolve_include(base, &pattern)? {
+                let included = Self::read_file(&include_path, including, module_files)?;
+                config.merge(included, &include_path)?;
+            }
         }
+        including.pop();
+
+        Ok(config)
     }
 
-    fn parse_for_template(txt: String) -> String {
-        txt.lines()
-            .map(|line| {
-                let line = line.trim();
+    /// Adds an included file's modules and hooks to this config. The hooks
+    /// of the including file run before the ones of the files it includes.
+    fn merge(&mut self, other: Self, other_path: &Path) -> Result<(), String> {
+        self.modules.extend(other.modules);
+        self.exclude.extend(other.exclude);
 
-                if line.starts_with("template ") {
-                    let mut line_splitted = line.split_whitespace();
-                    let template_file_opt = line_splitted.nth(1);
+        match (self.compression, other.compression) {
+            (Some(ours), Some(theirs)) if ours != theirs => {
+                return Err(format!(
+                    "{} sets a different compression than the file including it",
+                    other_path.display()
+                ));
+            }
+            (None, theirs) => self.compression = theirs,
+            _ => {}
+        }
 
-                    if let Some(template_file) = template_file_opt {
-                        let template_file_path = PathBuf::from(get_snap_config_dir())
-                            .join("templates")
-                            .join(template_file);
+        match (&mut self.hooks, other.hooks) {
+            (Some(ours), Some(theirs)) => ours
+                .merge(theirs)
+                .map_err(|err| format!("{} {err}", other_path.display()))?,
+            (ours @ None, theirs) => *ours = theirs,
+            (Some(_), None) => {}
+        }
 
-                        match fs::read_to_string(&template_file_path) {
-                            Ok(template_txt) => template_txt,
-                            Err(err) => {
-                                log(
-                                    logger::LogLevel::Error,
-                                    format!(
-                                        "Failed to read template {} ({err})",
-                                        template_file_path.display()
-                                    )
-                                    .as_str(),
-                                );
-                                "".to_string()
-                            }
-                        }
-                    } else {
-                        "".to_string()
-                    }
-                } else {
-                    line.to_string()
-                }
+        Ok(())
+    }
+
+    /// The load hooks of every module that has any, as stored in a snap
+    fn module_hooks(&self) -> BTreeMap<String, Hooks> {
+        self.modules
+            .iter()
+            .filter(|(_, module)| module.pre_load.is_some() || module.post_load.is_some())
+            .map(|(module_name, module)| {
+                (
+                    module_name.clone(),
+                    Hooks {
+                        pre_load: module.pre_load.clone(),
+                        post_load: module.post_load.clone(),
+                        ..Hooks::new(None, None)
+                    },
+                )
             })
-            .collect::<Vec<_>>()
-            .join("\n")
+            .collect()
+    }
+}
+
+/// Files an `include` entry names, relative to the including file. Globs may
+/// match nothing, but a plain path has to exist.
+fn resolve_include(base: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = base.join(pattern);
+
+    if !pattern.contains(['*', '?', '[']) {
+        if !path.is_file() {
+            return Err(format!("Included file {} does not exist", path.display()));
+        }
+        return Ok(vec![path]);
+    }
+
+    let matches = glob(&path.to_string_lossy())
+        .map_err(|err| format!("Include pattern {pattern} is not a valid glob ({err})"))?;
+    Ok(matches.flatten().filter(|path| path.is_file()).collect())
+}
+
+/// 1-based line and column of a byte offset into `txt`
+fn text_position(txt: &str, offset: usize) -> (usize, usize) {
+    let before = &txt[..offset.min(txt.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// Exclude globs of a module along with the config's global ones
+struct ExcludeRules {
+    patterns: Vec<glob::Pattern>,
+    root: Option<PathBuf>,
+}
+
+impl ExcludeRules {
+    /// Patterns with a `/` are matched against the whole path as it would be
+    /// recorded, anything else just against the file name, so `*.swp` works
+    /// at any depth
+    fn matches(&self, path: &Path) -> bool {
+        let path = match self.root {
+            Some(ref root) => unroot(root, path),
+            None => path.to_path_buf(),
+        };
+
+        self.patterns.iter().any(|pattern| {
+            if pattern.as_str().contains('/') {
+                pattern.matches_path(&path)
+            } else {
+                path.file_name()
+                    .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+            }
+        })
     }
 }
 
 impl ModuleConfig {
-    fn get_item_paths(&self) -> Vec<PathBuf> {
-        let mut items = Vec::new();
+    /// Resolves the include globs into every file, directory and symlink the
+    /// module tracks, minus anything matching its or the `global_exclude`
+    /// globs. Matched directories are walked recursively so nested and empty
+    /// directories end up in the snap as well. With a `root`, the globs are
+    /// resolved inside it instead of `/`. Each item comes with the path its
+    /// contents are read from, which differs from the item when a symlink was
+    /// followed inside the root. Also returns what was excluded.
+    fn get_items(
+        &self,
+        root: Option<&Path>,
+        global_exclude: &[String],
+    ) -> (BTreeMap<PathBuf, (ItemKind, PathBuf)>, Vec<PathBuf>) {
+        let mut items = BTreeMap::new();
+        let mut visited = HashSet::new();
+        let mut excluded = Vec::new();
+
+        let patterns = global_exclude
+            .iter()
+            .chain(&self.exclude)
+            .filter_map(|pattern| match glob::Pattern::new(pattern) {
+                Ok(pattern) => Some(pattern),
+                Err(err) => {
+                    log(
+                        logger::LogLevel::Warn,
+                        format!("Ignoring invalid exclude pattern {pattern} ({err})").as_str(),
+                    );
+                    None
+                }
+            })
+            .collect();
+        let rules = ExcludeRules {
+            patterns,
+            root: root.map(Path::to_path_buf),
+        };
 
         for item in &self.include {
-            for entry in glob(&item).expect("Never should happen") {
-                match entry {
-                    Ok(path) => {
-                        let meta = fs::metadata(&path).unwrap();
-                        if meta.is_file() {
-                            items.push(path);
-                        }
+            if let Err(err) =
+                self.collect_pattern(item, &rules, &mut items, &mut visited, &mut excluded)
+            {
+                log(
+                    logger::LogLevel::Warn,
+                    format!("Ignoring invalid include pattern {item} ({err})").as_str(),
+                );
+            }
+        }
+        (items, excluded)
+    }
+
+    /// Collects everything a single include pattern matches
+    fn collect_pattern(
+        &self,
+        item: &str,
+        rules: &ExcludeRules,
+        items: &mut BTreeMap<PathBuf, (ItemKind, PathBuf)>,
+        visited: &mut HashSet<PathBuf>,
+        excluded: &mut Vec<PathBuf>,
+    ) -> Result<(), glob::PatternError> {
+        let pattern = match rules.root {
+            Some(ref root) => reroot(root, Path::new(item)).to_string_lossy().into_owned(),
+            None => item.to_string(),
+        };
+
+        for path in glob(&pattern)?.flatten() {
+            let path = path::absolute(&path).unwrap_or(path);
+            let source = match rules.root {
+                Some(ref root) => match resolve_in_root(root, &path, false) {
+                    Some(source) => source,
+                    None => continue,
+                },
+                None => path.clone(),
+            };
+            self.collect_item(path, source, rules, items, visited, excluded);
+        }
+        Ok(())
+    }
+
+    /// Collects `path` and, for a directory, everything inside it. `source`
+    /// is where `path` actually lives on disk, which with a root is resolved
+    /// inside it so followed symlinks can't lead out onto the host.
+    fn collect_item(
+        &self,
+        path: PathBuf,
+        source: PathBuf,
+        rules: &ExcludeRules,
+        items: &mut BTreeMap<PathBuf, (ItemKind, PathBuf)>,
+        visited: &mut HashSet<PathBuf>,
+        excluded: &mut Vec<PathBuf>,
+    ) {
+        // An excluded directory is skipped along with everything inside it
+        if rules.matches(&path) {
+            excluded.push(path);
+            return;
+        }
+
+        let Ok(meta) = fs::symlink_metadata(&source) else {
+            return;
+        };
+
+        let (meta, source) = if meta.file_type().is_symlink() {
+            match self.symlinks {
+                SymlinkMode::Preserve => {
+                    if let Ok(target) = fs::read_link(&source) {
+                        items.insert(path, (ItemKind::Symlink(target), source));
                     }
-                    Err(_) => {
-                        continue;
+                    return;
+                }
+                SymlinkMode::Follow => {
+                    let source = match rules.root {
+                        Some(ref root) => match resolve_in_root(root, &source, true) {
+                            Some(source) => source,
+                            None => return,
+                        },
+                        None => source,
+                    };
+                    match fs::metadata(&source) {
+                        Ok(meta) => (meta, source),
+                        Err(_) => return,
                     }
                 }
             }
+        } else {
+            (meta, source)
+        };
+
+        if meta.is_file() {
+            items.insert(path, (ItemKind::File, source));
+        } else if meta.is_dir() {
+            // Following symlinks can lead back into a directory we already walked
+            if let Ok(real_path) = fs::canonicalize(&source)
+                && !visited.insert(real_path)
+            {
+                return;
+            }
+
+            if let Ok(entries) = fs::read_dir(&source) {
+                for entry in entries.flatten() {
+                    self.collect_item(
+                        path.join(entry.file_name()),
+                        entry.path(),
+                        rules,
+                        items,
+                        visited,
+                        excluded,
+                    );
+                }
+            }
+            items.insert(path, (ItemKind::Dir, source));
+        }
+    }
+}
+
+impl LegacySnapMetaData {
+    /// Converts to the current layout, handing each copy to `store` to add it
+    /// to the object store, and returns the copies that were stored. The
+    /// module of an item is the directory its copy was saved under. Copies
+    /// that can't be stored are left out. Attributes are left unset, since
+    /// the copies only have their own.
+    fn migrate(
+        self,
+        snap_dir: &Path,
+        mut store: impl FnMut(&Path) -> io::Result<(String, u64, u64)>,
+    ) -> (SnapMetaData, Vec<PathBuf>) {
+        let mut items = HashMap::new();
+        let mut stored = Vec::new();
+        let mut size = 0;
+        let mut stored_size = 0;
+
+        for (path, copy) in self.items {
+            let module = copy
+                .strip_prefix(snap_dir)
+                .ok()
+                .and_then(|relative| relative.components().next())
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            match store(&copy) {
+                Ok((hash, file_size, file_stored_size)) => {
+                    size += file_size;
+                    stored_size += file_stored_size;
+                    items.insert(
+                        path,
+                        SnapItem {
+                            module,
+                            kind: ItemKind::File,
+                            object: Some(hash),
+                            compression: Compression::None,
+                            attrs: None,
+                        },
+                    );
+                    stored.push(copy);
+                }
+                Err(err) => log(
+                    logger::LogLevel::Warn,
+                    format!(
+                        "Failed to migrate {}, it won't be restored ({err})",
+                        path.display()
+                    )
+                    .as_str(),
+                ),
+            }
         }
-        items
+
+        let mut meta = SnapMetaData::new(items, self.hooks, size, stored_size);
+        meta.timestamp = self.timestamp;
+        (meta, stored)
     }
 }
 
 impl SnapMetaData {
-    fn new(items: HashMap<PathBuf, PathBuf>, hooks: Option<Hooks>, size: u64) -> Self {
-        return Self {
+    fn new(
+        items: HashMap<PathBuf, SnapItem>,
+        hooks: Option<Hooks>,
+        size: u64,
+        stored_size: u64,
+    ) -> Self {
+        Self {
             timestamp: chrono::Local::now(),
             size,
+            stored_size,
             items,
             hooks,
-        };
+            module_hooks: BTreeMap::new(),
+            parent: None,
+            removed: BTreeSet::new(),
+            restored: None,
+        }
+    }
+
+    fn fetch(snaplog: &SnapLog, snap_name: &str) -> Option<Self> {
+        let snap_dir = snaplog.snaps.get(snap_name)?;
+        Self::from(&snap_dir.join("snap.json"))
+    }
+
+    /// Resolves the full set of items this snap restores by applying each
+    /// snap in its parent chain on top of the one before it
+    fn resolve_items(&self, snaplog: &SnapLog) -> Result<HashMap<PathBuf, SnapItem>, String> {
+        let mut chain = Vec::new();
+        let mut seen = HashSet::new();
+        let mut next = self.parent.clone();
+
+        while let Some(name) = next {
+            if !seen.insert(name.clone()) {
+                return Err(format!("Snap {name} is its own ancestor"));
+            }
+            let parent = Self::fetch(snaplog, &name)
+                .ok_or_else(|| format!("Failed to read parent snap {name}"))?;
+            next = parent.parent.clone();
+            chain.push(parent);
+        }
+
+        let mut items = HashMap::new();
+        for snap in chain.iter().rev().chain([self]) {
+            for removed in &snap.removed {
+                items.remove(removed);
+            }
+            items.extend(
+                snap.items
+                    .iter()
+                    .map(|(path, item)| (path.clone(), item.clone())),
+            );
+        }
+
+        Ok(items)
+    }
+
+    /// Merges the parent's own changes into this snap and points it at its
+    /// grandparent instead, so it still resolves to the same items once the
+    /// parent is gone
+    fn fold_parent(&mut self, parent: &SnapMetaData) {
+        for (path, item) in &parent.items {
+            if !self.removed.contains(path) && !self.items.contains_key(path) {
+                self.items.insert(path.clone(), item.clone());
+            }
+        }
+        self.removed.extend(parent.removed.iter().cloned());
+        self.parent = parent.parent.clone();
+        self.size += parent.size;
+        self.stored_size += parent.stored_size;
     }
 
     fn from(path: &PathBuf) -> Option<Self> {
@@ -206,64 +831,47 @@ impl SnapMetaData {
         Ok(())
     }
 
-    fn run_hook(&self, hook_type: HookType) -> HookStatus {
-        if let Some(ref hooks) = self.hooks {
-            let selected_hook = match hook_type {
-                HookType::Pre => &hooks.pre_load,
-                HookType::Post => &hooks.post_load,
-            };
-
-            if let Some(post_hook) = selected_hook {
-                let mut command_splitted = post_hook.split_whitespace();
-                let program = command_splitted.next();
-                let _args: Vec<&str> = command_splitted.collect();
-
-                if let Some(_program_txt) = program {
-                    let mut child = Command::new("sh")
-                        .arg("-c")
-                        .arg(post_hook)
-                        .stdout(Stdio::null())
-                        .spawn();
-
-                    if let Ok(ref mut child) = child {
-                        let status = child.wait();
+    /// Every hook a restore of `modules` runs at `hook_type`, along with the
+    /// module it belongs to. Global pre-hooks run before the modules' and
+    /// global post-hooks after them, so programs are torn down outside in and
+    /// brought back up inside out.
+    fn hooks_for<'a>(
+        &'a self,
+        hook_type: HookType,
+        modules: &BTreeSet<String>,
+    ) -> Vec<(Option<&'a str>, &'a HookStage)> {
+        let global = self
+            .hooks
+            .as_ref()
+            .and_then(|hooks| hooks.get(hook_type))
+            .map(|hook| (None, hook));
+        let module_hooks = self
+            .module_hooks
+            .iter()
+            .filter(|(module, _)| modules.contains(*module))
+            .filter_map(|(module, hooks)| {
+                hooks
+                    .get(hook_type)
+                    .map(|hook| (Some(module.as_str()), hook))
+            });
 
-                        if let Ok(status) = status {
-                            if status.success() {
-                                return HookStatus::Success;
-                            } else {
-                                return HookStatus::Error;
-                            }
-                        }
-                    } else {
-                        return HookStatus::Error;
-                    }
-                }
-            }
+        match hook_type {
+            HookType::Pre => global.into_iter().chain(module_hooks).collect(),
+            _ => module_hooks.chain(global).collect(),
         }
-        HookStatus::Nothing
     }
 
-    fn hook_exist(&self, hook_type: HookType) -> bool {
-        match self.hooks {
-            Some(ref hooks) => match hook_type {
-                HookType::Pre => {
-                    if let Some(_) = hooks.pre_load {
-                        true
-                    } else {
-                        false
-                    }
-                }
-                HookType::Post => {
-                    if let Some(_) = hooks.post_load {
-                        true
-                    } else {
-                        false
-                    }
-                }
-            },
-            None => false,
-        }
+    /// Fingerprints of every command that runs around restoring this snap
+    fn hook_fingerprints(&self) -> Vec<String> {
+        let modules = self.module_hooks.keys().cloned().collect();
+        let no_hooks = Hooks::new(None, None);
+        let settings = self.hooks.as_ref().unwrap_or(&no_hooks);
+        [HookType::Pre, HookType::Post]
+            .into_iter()
+            .flat_map(|hook_type| self.hooks_for(hook_type, &modules))
+            .flat_map(|(_, stage)| &stage.0)
+            .map(|entry| settings.fingerprint(&entry.run))
+            .collect()
     }
 }
 
@@ -272,11 +880,7 @@ impl SnapLog {
         let snap_config_dir = get_snap_config_dir();
         let snap_config_path = PathBuf::from(snap_config_dir).join("snaplog.json");
         if let Ok(file_txt) = fs::read_to_string(snap_config_path) {
-            if let Ok(log) = serde_json::from_str(&file_txt) {
-                log
-            } else {
-                None
-            }
+            serde_json::from_str(&file_txt).ok()
         } else {
             None
         }
@@ -306,435 +910,3573 @@ impl SnapLog {
     }
 }
 
-impl Hooks {
-    fn new(pre_hook: Option<String>, post_hook: Option<String>) -> Self {
-        return Self {
-            pre_load: pre_hook,
-            post_load: post_hook,
-        };
+impl TrustedHooks {
+    fn path() -> PathBuf {
+        PathBuf::from(get_snap_config_dir()).join("trusted_hooks.json")
     }
-}
-
-fn get_snap_config_dir() -> String {
-    let user_home_dir = std::env::var("HOME").expect("Failed to read HOME env variable");
-    let snaps_config_dir = user_home_dir + "/.config/snapsr";
-    snaps_config_dir
-}
 
-fn get_snaps_dir() -> String {
-    let user_home_dir = std::env::var("HOME").expect("Failed to read HOME env variable");
-    let snaps_dir = user_home_dir + "/.config/snapsr/snaps";
-    snaps_dir
-}
+    /// A missing or broken allowlist trusts nothing, so every hook gets asked about
+    fn fetch() -> Self {
+        fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|file_txt| serde_json::from_str(&file_txt).ok())
+            .unwrap_or_default()
+    }
 
-fn replace_component_in_path<P: AsRef<Path>>(path: P, name: &str, level: usize) -> Option<PathBuf> {
-    let path = path.as_ref();
-    let components: Vec<_> = path.components().collect();
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let json_txt = serde_json::to_string(self)?;
+        fs::write(Self::path(), json_txt)?;
+        Ok(())
+    }
 
-    if components.len() < level {
-        return None;
+    fn add(fingerprints: Vec<String>) -> Result<(), Box<dyn Error>> {
+        if fingerprints.is_empty() {
+            return Ok(());
+        }
+        let mut trusted = Self::fetch();
+        trusted.fingerprints.extend(fingerprints);
+        trusted.save()
     }
+}
 
-    let index_to_replace = components.len() - level;
+impl From<HookStageRepr> for HookStage {
+    fn from(repr: HookStageRepr) -> Self {
+        match repr {
+            HookStageRepr::One(entry) => Self(vec![entry]),
+            HookStageRepr::Many(entries) => Self(entries),
+        }
+    }
+}
 
-    let new_compoents = components.iter().enumerate().map(|(i, component)| {
-        if i == index_to_replace {
-            Component::Normal(OsStr::new(name))
-        } else {
-            *component
+impl From<HookEntryRepr> for HookEntry {
+    fn from(repr: HookEntryRepr) -> Self {
+        match repr {
+            HookEntryRepr::Command(run) => Self {
+                run,
+                on_failure: OnFailure::default(),
+            },
+            HookEntryRepr::Entry { run, on_failure } => Self { run, on_failure },
         }
-    });
+    }
+}
 
-    let new_path = new_compoents.fold(PathBuf::new(), |mut new_path, cur_comp| {
-        new_path.push(cur_comp);
-        new_path
-    });
+impl From<String> for HookStage {
+    fn from(command: String) -> Self {
+        Self(vec![HookEntryRepr::Command(command).into()])
+    }
+}
 
-    Some(new_path)
+impl HookStage {
+    /// One line per command, as shown when diffing snaps
+    fn describe(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|entry| match entry.on_failure {
+                OnFailure::Warn => entry.run.clone(),
+                OnFailure::Abort => format!("{} (abort on failure)", entry.run),
+                OnFailure::Ignore => format!("{} (ignore failure)", entry.run),
+            })
+            .collect()
+    }
+}
+
+impl Hooks {
+    fn new(pre_hook: Option<String>, post_hook: Option<String>) -> Self {
+        Self {
+            pre_load: pre_hook.map(HookStage::from),
+            post_load: post_hook.map(HookStage::from),
+            pre_snap: None,
+            post_snap: None,
+            post_delete: None,
+            post_rename: None,
+            shell: None,
+            cwd: None,
+            timeout: None,
+        }
+    }
+
+    fn shell(&self) -> &str {
+        self.shell.as_deref().unwrap_or("sh")
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        match self.timeout.unwrap_or(DEFAULT_HOOK_TIMEOUT) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// Identifies a command along with the shell and directory it runs in,
+    /// since changing either of those changes what the command does
+    fn fingerprint(&self, command: &str) -> String {
+        let identity =
+            serde_json::to_string(&(self.shell(), &self.cwd, command)).unwrap_or_default();
+        store::hash_bytes(identity.as_bytes())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pre_load.is_none() && self.post_load.is_none()
+    }
+
+    /// The hooks a snap keeps. Snap, delete and rename hooks are only ever
+    /// run from the config, so they are left out.
+    fn for_snap(&self) -> Self {
+        Self {
+            pre_snap: None,
+            post_snap: None,
+            post_delete: None,
+            post_rename: None,
+            ..self.clone()
+        }
+    }
+
+    /// Appends the commands of another config's hooks to these. Both may
+    /// only set `shell`, `cwd` and `timeout` if they agree on them.
+    fn merge(&mut self, other: Hooks) -> Result<(), String> {
+        if other.shell.is_some() && self.shell.is_some() && other.shell != self.shell {
+            return Err(String::from(
+                "sets a different [hooks] shell than the file including it",
+            ));
+        }
+        if other.cwd.is_some() && self.cwd.is_some() && other.cwd != self.cwd {
+            return Err(String::from(
+                "sets a different [hooks] cwd than the file including it",
+            ));
+        }
+        if other.timeout.is_some() && self.timeout.is_some() && other.timeout != self.timeout {
+            return Err(String::from(
+                "sets a different [hooks] timeout than the file including it",
+            ));
+        }
+        self.shell = self.shell.take().or(other.shell);
+        self.cwd = self.cwd.take().or(other.cwd);
+        self.timeout = self.timeout.or(other.timeout);
+
+        for (ours, theirs) in [
+            (&mut self.pre_load, other.pre_load),
+            (&mut self.post_load, other.post_load),
+            (&mut self.pre_snap, other.pre_snap),
+            (&mut self.post_snap, other.post_snap),
+            (&mut self.post_delete, other.post_delete),
+            (&mut self.post_rename, other.post_rename),
+        ] {
+            match (ours, theirs) {
+                (Some(ours), Some(theirs)) => ours.0.extend(theirs.0),
+                (ours @ None, theirs) => *ours = theirs,
+                (Some(_), None) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, hook_type: HookType) -> Option<&HookStage> {
+        match hook_type {
+            HookType::Pre => self.pre_load.as_ref(),
+            HookType::Post => self.post_load.as_ref(),
+            HookType::PreSnap => self.pre_snap.as_ref(),
+            HookType::PostSnap => self.post_snap.as_ref(),
+            HookType::PostDelete => self.post_delete.as_ref(),
+            HookType::PostRename => self.post_rename.as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self, hook_type: HookType) -> &mut Option<HookStage> {
+        match hook_type {
+            HookType::Pre => &mut self.pre_load,
+            HookType::Post => &mut self.post_load,
+            HookType::PreSnap => &mut self.pre_snap,
+            HookType::PostSnap => &mut self.post_snap,
+            HookType::PostDelete => &mut self.post_delete,
+            HookType::PostRename => &mut self.post_rename,
+        }
+    }
+}
+
+/// Runs a hook with the snap's context in `SNAPSR_*` environment variables.
+/// The paths being restored are written to a private file, one per line,
+/// which is passed both as the hook's stdin and in `SNAPSR_PATHS_FILE`. Its
+/// output is appended to `log_path` under `label`, and returned along with
+/// how it went.
+fn run_hook(
+    hook: &str,
+    settings: &Hooks,
+    mut env: Vec<(&str, String)>,
+    paths: &[&Path],
+    log_path: &Path,
+    label: &str,
+) -> (HookStatus, String) {
+    let log_file = open_hook_log(log_path, label, hook);
+    if let Err(ref err) = log_file {
+        log(
+            logger::LogLevel::Warn,
+            format!("Failed to write hook log, discarding the hook's output ({err})").as_str(),
+        );
+    }
+    let start = log_file
+        .as_ref()
+        .ok()
+        .and_then(|log_file| log_file.metadata().ok())
+        .map_or(0, |meta| meta.len());
+
+    let output_file = log_file
+        .as_ref()
+        .ok()
+        .and_then(|file| file.try_clone().ok());
+    let status = match write_paths_file(paths) {
+        Ok(paths_file) => {
+            let status = match fs::File::open(&paths_file) {
+                Ok(stdin) => {
+                    env.push(("SNAPSR_PATHS_FILE", paths_file.display().to_string()));
+                    hook::run(hook::HookCommand {
+                        shell: settings.shell(),
+                        command: hook,
+                        cwd: settings.cwd.as_deref(),
+                        env,
+                        stdin,
+                        output: output_file,
+                        timeout: settings.timeout(),
+                    })
+                }
+                Err(err) => {
+                    HookStatus::Error(format!("couldn't get its list of paths ready ({err})"))
+                }
+            };
+            let _ = fs::remove_file(&paths_file);
+            status
+        }
+        Err(err) => HookStatus::Error(format!("couldn't get its list of paths ready ({err})")),
+    };
+
+    let mut output = String::new();
+    if let Ok(mut log_file) = log_file {
+        output = read_hook_output(log_path, start).unwrap_or_default();
+        let result = match status {
+            HookStatus::Success => String::from("succeeded"),
+            HookStatus::Error(ref reason) => reason.clone(),
+            HookStatus::Nothing => String::from("empty"),
+        };
+        let _ = writeln!(log_file, "-- {result}\n");
+    }
+
+    (status, output)
+}
+
+fn get_hook_logs_dir() -> PathBuf {
+    PathBuf::from(get_snap_config_dir()).join("logs")
+}
+
+/// Picks the log file for this run's hooks, clearing out the oldest logs
+fn new_hook_log(name: &str) -> PathBuf {
+    let logs_dir = get_hook_logs_dir();
+
+    if let Ok(entries) = fs::read_dir(&logs_dir) {
+        let mut logs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        logs.sort();
+
+        let excess = (logs.len() + 1).saturating_sub(HOOK_LOG_HISTORY);
+        for old_log in &logs[..excess] {
+            let _ = fs::remove_file(old_log);
+        }
+    }
+
+    logs_dir.join(format!(
+        "{}-{name}.log",
+        Local::now().format("%Y%m%dT%H%M%S%.6f")
+    ))
+}
+
+/// Writes the paths a hook gets to a new file under the config directory.
+/// The file is created fresh with an unpredictable name and only the user
+/// can read it, so nobody else can plant or read it.
+fn write_paths_file(paths: &[&Path]) -> io::Result<PathBuf> {
+    let paths_txt: String = paths
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect();
+
+    let mut attempt = 0;
+    loop {
+        let nanos = Utc::now().timestamp_subsec_nanos();
+        let paths_file = PathBuf::from(get_snap_config_dir())
+            .join(format!(".hook-paths-{}-{nanos}-{attempt}", process::id()));
+
+        let mut file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&paths_file)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 16 => {
+                attempt += 1;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        if let Err(err) = file.write_all(paths_txt.as_bytes()) {
+            let _ = fs::remove_file(&paths_file);
+            return Err(err);
+        }
+        return Ok(paths_file);
+    }
+}
+
+/// Opens the run's log for appending and writes the header of a hook's entry,
+/// which the hook's output then follows
+fn open_hook_log(log_path: &Path, label: &str, command: &str) -> io::Result<fs::File> {
+    if let Some(logs_dir) = log_path.parent() {
+        fs::create_dir_all(logs_dir)?;
+    }
+
+    let mut log_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+
+    writeln!(log_file, "== {label} ({})", Local::now())?;
+    writeln!(log_file, "$ {command}")?;
+    Ok(log_file)
+}
+
+/// Reads what was appended to the log since `start`, which is the output of
+/// the hook that just ran
+fn read_hook_output(log_path: &Path, start: u64) -> io::Result<String> {
+    let mut log_file = fs::File::open(log_path)?;
+    log_file.seek(io::SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    log_file.read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn print_hook_output(output: &str) {
+    for line in output.lines() {
+        println!("    | {line}");
+    }
+}
+
+fn get_snap_config_dir() -> String {
+    let user_home_dir = std::env::var("HOME").expect("Failed to read HOME env variable");
+
+    user_home_dir + "/.config/snapsr"
+}
+
+fn get_snaps_dir() -> String {
+    let user_home_dir = std::env::var("HOME").expect("Failed to read HOME env variable");
+
+    user_home_dir + "/.config/snapsr/snaps"
+}
+
+fn replace_component_in_path<P: AsRef<Path>>(path: P, name: &str, level: usize) -> Option<PathBuf> {
+    let path = path.as_ref();
+    let components: Vec<_> = path.components().collect();
+
+    if components.len() < level {
+        return None;
+    }
+
+    let index_to_replace = components.len() - level;
+
+    let new_compoents = components.iter().enumerate().map(|(i, component)| {
+        if i == index_to_replace {
+            Component::Normal(OsStr::new(name))
+        } else {
+            *component
+        }
+    });
+
+    let new_path = new_compoents.fold(PathBuf::new(), |mut new_path, cur_comp| {
+        new_path.push(cur_comp);
+        new_path
+    });
+
+    Some(new_path)
+}
+
+impl ItemAttrs {
+    /// Reads the attributes of `path`. Symlinks are inspected themselves rather
+    /// than what they point to, matching how they are stored in the snap.
+    fn read(path: &Path, kind: &ItemKind) -> io::Result<Self> {
+        let (meta, names) = match kind {
+            ItemKind::Symlink(_) => (fs::symlink_metadata(path)?, xattr::list(path)),
+            ItemKind::File | ItemKind::Dir => (fs::metadata(path)?, xattr::list_deref(path)),
+        };
+
+        let mut xattrs = BTreeMap::new();
+        // Filesystems without xattr support are treated as having none
+        if let Ok(names) = names {
+            for name in names {
+                let value = match kind {
+                    ItemKind::Symlink(_) => xattr::get(path, &name),
+                    ItemKind::File | ItemKind::Dir => xattr::get_deref(path, &name),
+                };
+                if let Ok(Some(value)) = value {
+                    xattrs.insert(name.to_string_lossy().into_owned(), value);
+                }
+            }
+        }
+
+        Ok(Self {
+            mode: meta.mode() & 0o7777,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec() as u32,
+            xattrs,
+        })
+    }
+
+    /// Applies the recorded attributes to a restored item. Every attribute is
+    /// attempted even if an earlier one fails, and the first error is returned.
+    /// Ownership goes before the mode since a chown clears setuid/setgid bits,
+    /// and is left alone when not running as root.
+    fn apply(&self, path: &Path, kind: &ItemKind, options: &RestoreOptions) -> io::Result<()> {
+        let is_symlink = matches!(kind, ItemKind::Symlink(_));
+        let mut results = Vec::new();
+
+        for (name, value) in &self.xattrs {
+            results.push(xattr::set(path, name, value));
+        }
+
+        if options.owner && unsafe { libc::geteuid() } == 0 {
+            let current = fs::symlink_metadata(path)?;
+            if current.uid() != self.uid || current.gid() != self.gid {
+                results.push(unix_fs::lchown(path, Some(self.uid), Some(self.gid)));
+            }
+        }
+
+        if !is_symlink {
+            results.push(fs::set_permissions(
+                path,
+                fs::Permissions::from_mode(self.mode),
+            ));
+        }
+
+        if options.times {
+            let mtime = FileTime::from_unix_time(self.mtime, self.mtime_nsec);
+            if is_symlink {
+                results.push(filetime::set_symlink_file_times(path, mtime, mtime));
+            } else {
+                results.push(filetime::set_file_mtime(path, mtime));
+            }
+        }
+
+        results.into_iter().collect()
+    }
+}
+
+/// Checkpoints are kept out of the snap log so they never show up as snaps,
+/// one directory per checkpoint named so they sort oldest first
+fn get_checkpoints_dir() -> PathBuf {
+    PathBuf::from(get_snap_config_dir()).join("checkpoints")
+}
+
+fn list_checkpoints() -> io::Result<Vec<PathBuf>> {
+    let checkpoints_dir = get_checkpoints_dir();
+    if !checkpoints_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut checkpoints = fs::read_dir(checkpoints_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    checkpoints.sort();
+    Ok(checkpoints)
+}
+
+/// Moves an absolute path under `root`, e.g. `/etc/hosts` to `<root>/etc/hosts`
+fn reroot(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Resolves the symlinks along `path`, a path under `root`, as if `root` was
+/// `/`: absolute targets are looked up inside `root` and `..` never climbs
+/// above it. The last component is only resolved with `follow_last`. Gives
+/// up on symlink loops, like the OS does.
+fn resolve_in_root(root: &Path, path: &Path, follow_last: bool) -> Option<PathBuf> {
+    const MAX_LINKS: usize = 40;
+
+    let mut pending: Vec<PathBuf> = path
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .rev()
+        .map(|component| PathBuf::from(component.as_os_str()))
+        .collect();
+    let mut resolved = root.to_path_buf();
+    let mut links = 0;
+
+    while let Some(component) = pending.pop() {
+        match component.components().next() {
+            Some(Component::RootDir | Component::Prefix(_)) => resolved = root.to_path_buf(),
+            Some(Component::ParentDir) => {
+                if resolved != root {
+                    resolved.pop();
+                }
+            }
+            Some(Component::Normal(name)) => {
+                let candidate = resolved.join(name);
+                let is_last = pending.is_empty();
+                let is_symlink = fs::symlink_metadata(&candidate)
+                    .is_ok_and(|meta| meta.file_type().is_symlink());
+
+                if is_symlink && (follow_last || !is_last) {
+                    links += 1;
+                    if links > MAX_LINKS {
+                        return None;
+                    }
+                    // An absolute target starts over at the root
+                    let target = fs::read_link(&candidate).ok()?;
+                    pending.extend(
+                        target
+                            .components()
+                            .rev()
+                            .map(|component| PathBuf::from(component.as_os_str())),
+                    );
+                } else {
+                    resolved = candidate;
+                }
+            }
+            Some(Component::CurDir) | None => {}
+        }
+    }
+    Some(resolved)
+}
+
+/// Inverse of [`reroot`], records a path found under `root` as if `root` was `/`
+fn unroot(root: &Path, path: &Path) -> PathBuf {
+    match path.strip_prefix(root) {
+        Ok(relative) => Path::new("/").join(relative),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// A single change made while restoring, recorded so it can be reversed
+enum RestoreStep {
+    CreatedDir(PathBuf),
+    /// Whatever was at `path` got renamed to `backup` to make room
+    MovedAside {
+        path: PathBuf,
+        backup: PathBuf,
+    },
+    Staged {
+        path: PathBuf,
+        staged: PathBuf,
+    },
+    /// A staged item got renamed into place at this path
+    Swapped(PathBuf),
+}
+
+/// Restores items all or nothing. Every file and symlink is first written to
+/// a temporary name next to its target and only renamed over it once all of
+/// them staged fine. Each step is journaled so a failure at any point can be
+/// rolled back to exactly what was there before.
+#[derive(Default)]
+struct RestoreTransaction {
+    journal: Vec<RestoreStep>,
+    /// Create missing parent directories instead of failing on them
+    create_parents: bool,
+    /// Let files and symlinks replace directories that aren't empty. What was
+    /// inside them is gone for good, since checkpoints don't keep it.
+    replace_dirs: bool,
+    /// Paths created by the restore being undone. Directories holding nothing
+    /// but these can be replaced without `replace_dirs`.
+    removed: BTreeSet<PathBuf>,
+}
+
+/// Fails if anything inside `dir` isn't listed in `paths`
+fn check_only_contains(dir: &Path, paths: &BTreeSet<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !paths.contains(&path) {
+            return Err(io::Error::other(format!(
+                "{} wasn't created by the restore",
+                path.display()
+            )));
+        }
+        if entry.file_type()?.is_dir() {
+            check_only_contains(&path, paths)?;
+        }
+    }
+    Ok(())
+}
+
+/// Hidden sibling of `path` used to stage or back up an item during a restore
+fn sibling_path(path: &Path, tag: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.snapsr-{tag}-{}", process::id()))
+}
+
+impl RestoreTransaction {
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|ancestor| fs::symlink_metadata(ancestor).is_err())
+            .collect();
+
+        for dir in missing.into_iter().rev() {
+            fs::create_dir(dir)?;
+            self.journal
+                .push(RestoreStep::CreatedDir(dir.to_path_buf()));
+        }
+        Ok(())
+    }
+
+    fn move_aside(&mut self, path: &Path) -> io::Result<()> {
+        let backup = sibling_path(path, "old");
+        fs::rename(path, &backup)?;
+        self.journal.push(RestoreStep::MovedAside {
+            path: path.to_path_buf(),
+            backup,
+        });
+        Ok(())
+    }
+
+    /// Moves the paths created by the restore being undone out of the way. A
+    /// directory only goes if everything in it was created by that restore
+    /// too, since anything else in it would be lost.
+    fn remove(&mut self) -> Result<(), (PathBuf, io::Error)> {
+        // Sorted, so directories go before what's inside them
+        for path in self.removed.clone() {
+            // Already moved along with a directory above it or replaced by an item
+            let Ok(meta) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+
+            if meta.is_dir() {
+                check_only_contains(&path, &self.removed).map_err(|err| (path.clone(), err))?;
+            }
+            self.move_aside(&path).map_err(|err| (path.clone(), err))?;
+        }
+        Ok(())
+    }
+
+    /// Gets an item ready to be swapped in. Directories are created right
+    /// away since the items inside them need somewhere to be staged.
+    fn stage(&mut self, path: &Path, item: &SnapItem) -> io::Result<()> {
+        if self.create_parents
+            && let Some(parent) = path.parent()
+        {
+            self.create_dir_all(parent)?;
+        }
+
+        let existing = fs::symlink_metadata(path).ok();
+
+        if item.kind == ItemKind::Dir {
+            match existing {
+                Some(meta) if meta.is_dir() => return Ok(()),
+                Some(_) => self.move_aside(path)?,
+                None => {}
+            }
+            return self.create_dir_all(path);
+        }
+
+        if !self.replace_dirs
+            && let Some(ref meta) = existing
+            && meta.is_dir()
+            && check_only_contains(path, &self.removed).is_err()
+        {
+            return Err(io::Error::other(
+                "a directory that isn't empty is in the way, pass --force to replace it",
+            ));
+        }
+
+        let staged = sibling_path(path, "new");
+        self.journal.push(RestoreStep::Staged {
+            path: path.to_path_buf(),
+            staged: staged.clone(),
+        });
+
+        match &item.kind {
+            ItemKind::Symlink(target) => unix_fs::symlink(target, &staged),
+            _ => {
+                let object = item.object.as_ref().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "snap has no stored copy")
+                })?;
+                let mut contents = store::open_object(object, item.compression)?;
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .mode(0o600)
+                    .open(&staged)?;
+                io::copy(&mut contents, &mut file)?;
+
+                // The file is only readable by its owner until it gets the
+                // recorded mode, so it's never swapped in more open than that
+                let Some(ref attrs) = item.attrs else {
+                    return Ok(());
+                };
+                file.set_permissions(fs::Permissions::from_mode(attrs.mode))?;
+                for (name, value) in &attrs.xattrs {
+                    // Failures are reported once the attributes are applied
+                    // to the restored item
+                    let _ = xattr::set(&staged, name, value);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Renames every staged item over its target, keeping what was there as
+    /// a backup until the whole restore went through
+    fn commit(&mut self) -> Result<(), (PathBuf, io::Error)> {
+        let staged: Vec<(PathBuf, PathBuf)> = self
+            .journal
+            .iter()
+            .filter_map(|step| match step {
+                RestoreStep::Staged { path, staged } => Some((path.clone(), staged.clone())),
+                _ => None,
+            })
+            .collect();
+
+        for (path, staged) in staged {
+            if fs::symlink_metadata(&path).is_ok() {
+                self.move_aside(&path).map_err(|err| (path.clone(), err))?;
+            }
+            fs::rename(&staged, &path).map_err(|err| (path.clone(), err))?;
+            self.journal.push(RestoreStep::Swapped(path));
+        }
+
+        Ok(())
+    }
+
+    /// Drops the backups of everything that got replaced
+    fn finish(self) {
+        for step in self.journal {
+            if let RestoreStep::MovedAside { backup, .. } = step {
+                let result = if backup.is_dir() && !backup.is_symlink() {
+                    fs::remove_dir_all(&backup)
+                } else {
+                    fs::remove_file(&backup)
+                };
+
+                if let Err(err) = result {
+                    log(
+                        logger::LogLevel::Warn,
+                        format!("Failed to remove backup {} ({err})", backup.display()).as_str(),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Undoes every step in reverse and returns the ones that couldn't be
+    fn rollback(self) -> Vec<(PathBuf, io::Error)> {
+        let mut errors = Vec::new();
+
+        for step in self.journal.into_iter().rev() {
+            let (path, result) = match step {
+                RestoreStep::Swapped(path) => {
+                    let result = fs::remove_file(&path);
+                    (path, result)
+                }
+                RestoreStep::MovedAside { path, backup } => {
+                    let result = fs::rename(&backup, &path);
+                    (path, result)
+                }
+                RestoreStep::Staged { staged, .. } => {
+                    let result = match fs::remove_file(&staged) {
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                        result => result,
+                    };
+                    (staged, result)
+                }
+                RestoreStep::CreatedDir(path) => {
+                    let result = fs::remove_dir(&path);
+                    (path, result)
+                }
+            };
+
+            if let Err(err) = result {
+                errors.push((path, err));
+            }
+        }
+
+        errors
+    }
+}
+
+/// Captures whatever currently sits at each path a restore is about to write,
+/// so the restore can be undone. Paths that don't exist yet are recorded as
+/// such and get removed again on undo.
+fn create_checkpoint(
+    snap_name: &str,
+    snap_meta: &SnapMetaData,
+    items: &HashMap<PathBuf, SnapItem>,
+) -> Result<PathBuf, String> {
+    let _store_lock = store::StoreLock::shared()
+        .map_err(|err| format!("Failed to lock the object store ({err})"))?;
+
+    let mut checkpoint_items = HashMap::new();
+    let mut missing = BTreeSet::new();
+    let mut size = 0;
+    let mut stored_size = 0;
+
+    for (path, item) in items {
+        let kind = match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_dir() => ItemKind::Dir,
+            Ok(meta) if meta.file_type().is_symlink() => match fs::read_link(path) {
+                Ok(target) => ItemKind::Symlink(target),
+                Err(err) => {
+                    return Err(format!("Failed to read link {} ({err})", path.display()));
+                }
+            },
+            Ok(meta) if meta.is_file() => ItemKind::File,
+            Ok(_) => return Err(format!("{} is not a regular file", path.display())),
+            // A file where the snap has a directory means nothing is inside it
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+                ) =>
+            {
+                missing.insert(path.clone());
+                continue;
+            }
+            Err(err) => return Err(format!("Failed to read {} ({err})", path.display())),
+        };
+
+        let object = match kind {
+            ItemKind::File => {
+                let (hash, file_size, file_stored_size) =
+                    store::store_file(path, Compression::None)
+                        .map_err(|err| format!("Failed to save {} ({err})", path.display()))?;
+                size += file_size;
+                stored_size += file_stored_size;
+                Some(hash)
+            }
+            ItemKind::Dir | ItemKind::Symlink(_) => None,
+        };
+
+        let attrs = ItemAttrs::read(path, &kind).ok();
+
+        checkpoint_items.insert(
+            path.clone(),
+            SnapItem {
+                module: item.module.clone(),
+                kind,
+                object,
+                compression: Compression::None,
+                attrs,
+            },
+        );
+    }
+
+    let mut checkpoint =
+        SnapMetaData::new(checkpoint_items, snap_meta.hooks.clone(), size, stored_size);
+    // Only the hooks of modules this restore touches need rerunning on undo
+    checkpoint.module_hooks = snap_meta
+        .module_hooks
+        .iter()
+        .filter(|(module, _)| items.values().any(|item| &item.module == *module))
+        .map(|(module, hooks)| (module.clone(), hooks.clone()))
+        .collect();
+    checkpoint.removed = missing;
+    checkpoint.restored = Some(snap_name.into());
+
+    let checkpoint_dir =
+        get_checkpoints_dir().join(checkpoint.timestamp.format("%Y%m%dT%H%M%S%.6f").to_string());
+    fs::create_dir_all(&checkpoint_dir)
+        .map_err(|err| format!("Failed to create checkpoint directory ({err})"))?;
+    checkpoint
+        .save(&checkpoint_dir.join("snap.json"))
+        .map_err(|err| format!("Failed to save checkpoint ({err})"))?;
+
+    // Only the newest checkpoints are kept, their objects go with the next cleanup
+    if let Ok(checkpoints) = list_checkpoints() {
+        let excess = checkpoints.len().saturating_sub(CHECKPOINT_HISTORY);
+        for old_checkpoint in &checkpoints[..excess] {
+            if let Err(err) = fs::remove_dir_all(old_checkpoint) {
+                log(
+                    logger::LogLevel::Warn,
+                    format!(
+                        "Failed to remove old checkpoint {} ({err})",
+                        old_checkpoint.display()
+                    )
+                    .as_str(),
+                );
+            }
+        }
+    }
+
+    Ok(checkpoint_dir)
+}
+
+/// Deletes every object that no registered snap refers to any more and
+/// returns how many were removed. Nothing is removed if a snap's metadata
+/// can't be read, since the objects it references would be lost with it.
+/// Waits for snaps that are still adding objects, so the caller must not
+/// hold the store lock itself.
+fn collect_unreferenced_objects() -> usize {
+    let _store_lock = match store::StoreLock::exclusive() {
+        Ok(lock) => lock,
+        Err(err) => {
+            log(
+                logger::LogLevel::Warn,
+                format!("Failed to lock the object store, skipping object cleanup ({err})")
+                    .as_str(),
+            );
+            return 0;
+        }
+    };
+
+    let Some(snaplog) = SnapLog::fetch() else {
+        log(logger::LogLevel::Error, "Failed to read snap log");
+        return 0;
+    };
+
+    let checkpoints = match list_checkpoints() {
+        Ok(checkpoints) => checkpoints,
+        Err(err) => {
+            log(
+                logger::LogLevel::Warn,
+                format!("Failed to read checkpoints, skipping object cleanup ({err})").as_str(),
+            );
+            return 0;
+        }
+    };
+
+    let snap_dirs = snaplog
+        .snaps
+        .iter()
+        .map(|(name, snap_dir)| (name.clone(), snap_dir.clone()))
+        .chain(checkpoints.into_iter().map(|checkpoint_dir| {
+            (
+                format!("checkpoint {}", checkpoint_dir.display()),
+                checkpoint_dir,
+            )
+        }));
+
+    let mut referenced = HashSet::new();
+    for (name, snap_dir) in snap_dirs {
+        match SnapMetaData::from(&snap_dir.join("snap.json")) {
+            Some(snap_meta) => {
+                referenced.extend(snap_meta.items.into_values().filter_map(|item| {
+                    item.object
+                        .and_then(|hash| store::object_path(&hash, item.compression).ok())
+                }))
+            }
+            None => {
+                log(
+                    logger::LogLevel::Warn,
+                    format!("Failed to read {name}'s metadata, skipping object cleanup").as_str(),
+                );
+                return 0;
+            }
+        }
+    }
+
+    match store::collect_garbage(&referenced) {
+        Ok((0, _)) => 0,
+        Ok((removed, reclaimed)) => {
+            log(
+                logger::LogLevel::Success,
+                format!("Removed {removed} unreferenced object(s), freed {reclaimed} bytes")
+                    .as_str(),
+            );
+            removed
+        }
+        Err(err) => {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to clean up unreferenced objects ({err})").as_str(),
+            );
+            0
+        }
+    }
+}
+
+/// Folds a snap into every snap that was taken on top of it, so it can be
+/// deleted or overwritten without breaking them. Returns how many were folded.
+fn fold_into_children(snaplog: &SnapLog, snap_name: &str) -> Result<usize, String> {
+    let children: Vec<(&PathBuf, SnapMetaData)> = snaplog
+        .snaps
+        .iter()
+        .filter(|(name, _)| name.as_str() != snap_name)
+        .filter_map(|(_, snap_dir)| {
+            SnapMetaData::from(&snap_dir.join("snap.json")).map(|meta| (snap_dir, meta))
+        })
+        .filter(|(_, meta)| meta.parent.as_deref() == Some(snap_name))
+        .collect();
+
+    if children.is_empty() {
+        return Ok(0);
+    }
+
+    let parent = SnapMetaData::fetch(snaplog, snap_name)
+        .ok_or_else(|| format!("Failed to read {snap_name}'s metadata"))?;
+
+    let folded = children.len();
+    for (snap_dir, mut child) in children {
+        child.fold_parent(&parent);
+        child
+            .save(&snap_dir.join("snap.json"))
+            .map_err(|err| format!("Failed to update {} ({err})", snap_dir.display()))?;
+    }
+
+    Ok(folded)
+}
+
+/// Moves snaps saved before the object store into it, so they can be
+/// listed and restored again, and removes the copies they were kept as
+pub fn migrate_legacy_snaps() {
+    let Some(mut snaplog) = SnapLog::fetch() else {
+        return;
+    };
+    if snaplog.migrated {
+        return;
+    }
+
+    let mut migrated = true;
+    for (snap_name, snap_dir) in &snaplog.snaps {
+        let meta_path = snap_dir.join("snap.json");
+        let Ok(txt) = fs::read_to_string(&meta_path) else {
+            continue;
+        };
+        if serde_json::from_str::<SnapMetaData>(&txt).is_ok() {
+            continue;
+        }
+        let Ok(legacy) = serde_json::from_str::<LegacySnapMetaData>(&txt) else {
+            continue;
+        };
+        let Ok(_store_lock) = store::StoreLock::shared() else {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to lock the object store to migrate snap {snap_name}").as_str(),
+            );
+            migrated = false;
+            continue;
+        };
+
+        let (meta, copies) =
+            legacy.migrate(snap_dir, |copy| store::store_file(copy, Compression::None));
+        if let Err(err) = meta.save(&meta_path) {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to migrate snap {snap_name} ({err})").as_str(),
+            );
+            migrated = false;
+            continue;
+        }
+
+        for copy in copies {
+            remove_legacy_copy(snap_dir, &copy);
+        }
+        log(
+            logger::LogLevel::Info,
+            format!("Migrated snap {snap_name} to the object store").as_str(),
+        );
+    }
+
+    if migrated {
+        snaplog.migrated = true;
+        if snaplog.save().is_err() {
+            log(logger::LogLevel::Warn, "Failed to save snap log");
+        }
+    }
+}
+
+/// Removes a copy a snap used to keep its contents in, along with the
+/// directories that are left empty, never touching anything outside the
+/// snap's own directory
+fn remove_legacy_copy(snap_dir: &Path, copy: &Path) {
+    if !copy.starts_with(snap_dir)
+        || copy
+            .components()
+            .any(|component| component == Component::ParentDir)
+    {
+        return;
+    }
+    if fs::remove_file(copy).is_err() {
+        return;
+    }
+    for dir in copy.ancestors().skip(1) {
+        if dir == snap_dir || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
 }
 
 pub fn cmd_snap(
     snap_name: String,
     snap_config_path: Option<PathBuf>,
-    pre_hook: Option<String>,
-    post_hook: Option<String>,
+    pre_hook: Option<String>,
+    post_hook: Option<String>,
+    parent: Option<String>,
+    root: Option<PathBuf>,
+    verbose: bool,
+) {
+    let mut overwrite = false;
+    let snaplog = match SnapLog::fetch() {
+        Some(snaplog) => {
+            if let Some(ref parent_name) = parent {
+                if *parent_name == snap_name {
+                    log(logger::LogLevel::Error, "A snap can't be its own parent");
+                    return;
+                }
+
+                if !snaplog.exist(parent_name) {
+                    log(
+                        logger::LogLevel::Error,
+                        format!("Parent snap {parent_name} does not exist").as_str(),
+                    );
+                    return;
+                }
+            }
+
+            if snaplog.exist(snap_name.as_str()) {
+                let mut input = String::new();
+                log(
+                    logger::LogLevel::Info,
+                    format!("Snap {snap_name} already exist. Do you wish to overwrite (y/N)? ")
+                        .as_str(),
+                );
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut input).unwrap();
+                let input = input.trim();
+                let input = input.to_lowercase();
+
+                if input != "y" && input != "yes" {
+                    log(logger::LogLevel::Info, "aborting");
+                    return;
+                }
+                overwrite = true;
+            }
+            snaplog
+        }
+        None => {
+            log(logger::LogLevel::Error, "Failed to read snap log");
+            return;
+        }
+    };
+
+    let snap = match SnapConfig::load(snap_config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to read snap config ({err})").as_str(),
+            );
+            return;
+        }
+    };
+
+    let mut hook_context = HookContext::new(&snap_name, "snap");
+    hook_context.modules = snap.modules.keys().cloned().collect();
+    let pre_snap = run_config_hooks(&snap, HookType::PreSnap, &hook_context, verbose);
+    if pre_snap == HooksOutcome::Aborted {
+        log(
+            logger::LogLevel::Error,
+            format!("Aborting snap {snap_name}, nothing was saved").as_str(),
+        );
+        process::exit(1);
+    }
+
+    if overwrite {
+        match fold_into_children(&snaplog, &snap_name) {
+            Ok(0) => {}
+            Ok(folded) => log(
+                logger::LogLevel::Info,
+                format!("Folded {snap_name} into {folded} snap(s) based on it").as_str(),
+            ),
+            Err(err) => {
+                log(
+                    logger::LogLevel::Error,
+                    format!("Failed to fold {snap_name} into the snaps based on it ({err})")
+                        .as_str(),
+                );
+                return;
+            }
+        }
+
+        if let Some(log_entry) = snaplog.snaps.get(&snap_name) {
+            fs::remove_dir_all(log_entry).unwrap_or_else(|err| {
+                log(
+                    logger::LogLevel::Error,
+                    format!("Failed to clear existing snap ({err})").as_str(),
+                );
+                process::exit(1);
+            });
+        }
+    }
+
+    let parent_items = match parent {
+        Some(ref parent_name) => {
+            let parent_items = SnapLog::fetch()
+                .ok_or_else(|| "Failed to read snap log".to_string())
+                .and_then(|snaplog| {
+                    SnapMetaData::fetch(&snaplog, parent_name)
+                        .ok_or_else(|| format!("Failed to read {parent_name}'s metadata"))
+                        .and_then(|parent_meta| parent_meta.resolve_items(&snaplog))
+                });
+
+            match parent_items {
+                Ok(items) => items,
+                Err(err) => {
+                    log(logger::LogLevel::Error, err.as_str());
+                    return;
+                }
+            }
+        }
+        None => HashMap::new(),
+    };
+
+    let snap_dir = get_snaps_dir();
+    let snap_dir = path::Path::new(&snap_dir).join(&snap_name);
+
+    if fs::create_dir_all(&snap_dir).is_err() {
+        log(logger::LogLevel::Error, "Failed to create snap directory");
+        return;
+    }
+
+    let mut snap_items: HashMap<PathBuf, SnapItem> = HashMap::new();
+    let mut seen_items = HashMap::new();
+    let mut unchanged_items_amount = 0;
+    let mut size_of_snap = 0;
+    let mut stored_size_of_snap = 0;
+    let mut total_items = 0;
+    let mut snapped_items_amount = 0;
+
+    let store_lock = match store::StoreLock::shared() {
+        Ok(lock) => lock,
+        Err(err) => {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to lock the object store ({err})").as_str(),
+            );
+            return;
+        }
+    };
+
+    for (module_name, module) in &snap.modules {
+        let compression = module.compression.or(snap.compression).unwrap_or_default();
+        let (items, excluded) = module.get_items(root.as_deref(), &snap.exclude);
+        total_items += items.len();
+
+        if verbose {
+            for path in &excluded {
+                log(
+                    logger::LogLevel::Info,
+                    format!("Excluded {} ({module_name})", path.display()).as_str(),
+                );
+            }
+        }
+
+        log(
+            logger::LogLevel::Info,
+            format!("{module_name}: {} items", items.len()).as_str(),
+        );
+
+        for (path, (kind, source)) in items {
+            // Paths under the root are recorded as if it was `/`
+            let item = match root {
+                Some(ref root) => unroot(root, &path),
+                None => path,
+            };
+
+            let mut sizes = (0, 0);
+            let object = match kind {
+                ItemKind::File => match store::store_file(&source, compression) {
+                    Ok((hash, size, stored_size)) => {
+                        sizes = (size, stored_size);
+                        Some(hash)
+                    }
+                    Err(err) => {
+                        log(
+                            logger::LogLevel::Error,
+                            format!(
+                                "Failed to snap {}, skipping ({module_name}) ({err})",
+                                item.display()
+                            )
+                            .as_str(),
+                        );
+                        continue;
+                    }
+                },
+                ItemKind::Dir | ItemKind::Symlink(_) => None,
+            };
+
+            let attrs = ItemAttrs::read(&source, &kind)
+                .map_err(|err| {
+                    log(
+                        logger::LogLevel::Warn,
+                        format!(
+                            "Failed to read attributes of {}, they won't be restored ({err})",
+                            item.display()
+                        )
+                        .as_str(),
+                    );
+                })
+                .ok();
+
+            let snap_item = SnapItem {
+                module: module_name.clone(),
+                kind,
+                object,
+                compression,
+                attrs,
+            };
+
+            seen_items.insert(item.clone(), module_name.as_str());
+            snapped_items_amount += 1;
+
+            // Incremental snaps only keep what differs from their parent
+            if parent_items.get(&item) == Some(&snap_item) {
+                unchanged_items_amount += 1;
+                continue;
+            }
+
+            if verbose {
+                log(
+                    logger::LogLevel::Success,
+                    format!("Snapped {} ({module_name})", item.display()).as_str(),
+                );
+            }
+
+            size_of_snap += sizes.0;
+            stored_size_of_snap += sizes.1;
+            snap_items.insert(item, snap_item);
+        }
+    }
+
+    let removed_items: BTreeSet<PathBuf> = parent_items
+        .into_keys()
+        .filter(|item| !seen_items.contains_key(item))
+        .collect();
+
+    let hooks = if pre_hook.is_some() || post_hook.is_some() {
+        let mut hooks = Hooks::new(pre_hook, post_hook);
+        if let Some(ref config_hooks) = snap.hooks {
+            hooks.shell = config_hooks.shell.clone();
+            hooks.cwd = config_hooks.cwd.clone();
+            hooks.timeout = config_hooks.timeout;
+        }
+        Some(hooks)
+    } else {
+        snap.hooks.as_ref().map(Hooks::for_snap)
+    };
+
+    let mut snap_meta_data =
+        SnapMetaData::new(snap_items, hooks, size_of_snap, stored_size_of_snap);
+    snap_meta_data.module_hooks = snap.module_hooks();
+    let removed_items_amount = removed_items.len();
+    snap_meta_data.parent = parent.clone();
+    snap_meta_data.removed = removed_items;
+
+    if snap_meta_data.save(&snap_dir.join("snap.json")).is_ok() {
+        if let Some(mut snaplog) = SnapLog::fetch() {
+            snaplog.snaps.insert(snap_name.clone(), snap_dir);
+            if snaplog.save().is_ok() {
+                log(
+                    logger::LogLevel::Success,
+                    format!("Saved Snap {snapped_items_amount}/{total_items} item(s)").as_str(),
+                );
+                if let Err(err) = TrustedHooks::add(snap_meta_data.hook_fingerprints()) {
+                    log(
+                        logger::LogLevel::Warn,
+                        format!(
+                            "Failed to trust {snap_name}'s hooks, restoring it will ask about them ({err})"
+                        )
+                        .as_str(),
+                    );
+                }
+                if let Some(parent_name) = parent {
+                    log(
+                        logger::LogLevel::Info,
+                        format!(
+                            "{} changed, {unchanged_items_amount} unchanged and {removed_items_amount} removed since {parent_name}",
+                            snapped_items_amount - unchanged_items_amount
+                        )
+                        .as_str(),
+                    );
+                }
+
+                hook_context.items = seen_items
+                    .iter()
+                    .map(|(path, module)| (path.as_path(), *module))
+                    .collect();
+                let post_snap = run_config_hooks(&snap, HookType::PostSnap, &hook_context, verbose);
+
+                drop(store_lock);
+                collect_unreferenced_objects();
+
+                if pre_snap != HooksOutcome::Success || post_snap != HooksOutcome::Success {
+                    process::exit(1);
+                }
+            } else {
+                log(
+                    logger::LogLevel::Error,
+                    "Failed to save snap log, this snap will be unusable",
+                );
+            }
+        } else {
+            log(
+                logger::LogLevel::Error,
+                "Failed to save snap log, this snap will be unusable",
+            );
+        }
+    } else {
+        log(
+            logger::LogLevel::Error,
+            "Failed to save snap log, this snap will be unusable",
+        );
+    }
+}
+
+/// Shows the snap's hooks that aren't trusted on this machine yet and asks
+/// whether to run them, remembering them when approved. Snaps can come from
+/// anywhere, so a hook is never run without being written here or approved.
+fn approve_hooks(snap_name: &str, snap_meta: &SnapMetaData, modules: &BTreeSet<String>) -> bool {
+    let mut trusted = TrustedHooks::fetch();
+    let no_hooks = Hooks::new(None, None);
+    let settings = snap_meta.hooks.as_ref().unwrap_or(&no_hooks);
+
+    let mut unknown = Vec::new();
+    for (stage, hook_type) in [("pre_load", HookType::Pre), ("post_load", HookType::Post)] {
+        for (module, stage_hooks) in snap_meta.hooks_for(hook_type, modules) {
+            for entry in &stage_hooks.0 {
+                let fingerprint = settings.fingerprint(&entry.run);
+                if !trusted.fingerprints.contains(&fingerprint) {
+                    let stage = match module {
+                        Some(module) => format!("{stage} of {module}"),
+                        None => stage.to_string(),
+                    };
+                    unknown.push((stage, &entry.run, fingerprint));
+                }
+            }
+        }
+    }
+
+    if unknown.is_empty() {
+        return true;
+    }
+
+    log(
+        logger::LogLevel::Warn,
+        format!("{snap_name} has hooks that were never approved on this machine").as_str(),
+    );
+    if let Some(ref cwd) = settings.cwd {
+        println!("    (run in {})", cwd.display());
+    }
+    for (stage, command, _) in &unknown {
+        println!(
+            "    {stage}: {} -c {}",
+            shell_quote(settings.shell()),
+            shell_quote(command)
+        );
+    }
+
+    let mut input = String::new();
+    log(logger::LogLevel::Info, "Do you wish to run them (y/N)? ");
+    io::stdout().flush().unwrap();
+    if io::stdin().read_line(&mut input).is_err() {
+        return false;
+    }
+    let input = input.trim().to_lowercase();
+    if input != "y" && input != "yes" {
+        return false;
+    }
+
+    trusted
+        .fingerprints
+        .extend(unknown.into_iter().map(|(_, _, fingerprint)| fingerprint));
+    if let Err(err) = trusted.save() {
+        log(
+            logger::LogLevel::Warn,
+            format!("Failed to remember the approved hooks ({err})").as_str(),
+        );
+    }
+    true
+}
+
+/// Drops every hook that would run around restoring files
+fn clear_restore_hooks(snap_meta: &mut SnapMetaData) -> bool {
+    let mut cleared = !snap_meta.module_hooks.is_empty();
+    snap_meta.module_hooks.clear();
+    if let Some(ref mut hooks) = snap_meta.hooks {
+        cleared |= hooks.pre_load.take().is_some();
+        cleared |= hooks.post_load.take().is_some();
+    }
+    cleared
+}
+
+fn run_restore_hooks(
+    snap_meta: &SnapMetaData,
+    hook_type: HookType,
+    context: &HookContext,
+    verbose: bool,
+) -> HooksOutcome {
+    let no_hooks = Hooks::new(None, None);
+    let settings = snap_meta.hooks.as_ref().unwrap_or(&no_hooks);
+    let hooks = snap_meta.hooks_for(hook_type, &context.modules);
+    run_hooks(settings, hooks, hook_type, context, verbose)
+}
+
+/// Runs the config's own hook for `hook_type`, if it has one
+fn run_config_hooks(
+    config: &SnapConfig,
+    hook_type: HookType,
+    context: &HookContext,
+    verbose: bool,
+) -> HooksOutcome {
+    match config.hooks {
+        Some(ref settings) => match settings.get(hook_type) {
+            Some(hook) => run_hooks(settings, vec![(None, hook)], hook_type, context, verbose),
+            None => HooksOutcome::Success,
+        },
+        None => HooksOutcome::Success,
+    }
+}
+
+/// Runs each hook in order and reports how it went. `settings` holds the
+/// shell, working directory and timeout they all share.
+fn run_hooks(
+    settings: &Hooks,
+    hooks: Vec<(Option<&str>, &HookStage)>,
+    hook_type: HookType,
+    context: &HookContext,
+    verbose: bool,
+) -> HooksOutcome {
+    let (stage, phase) = match hook_type {
+        HookType::Pre => ("Pre-hook", "pre_load"),
+        HookType::Post => ("Post-hook", "post_load"),
+        HookType::PreSnap => ("Pre-snap hook", "pre_snap"),
+        HookType::PostSnap => ("Post-snap hook", "post_snap"),
+        HookType::PostDelete => ("Post-delete hook", "post_delete"),
+        HookType::PostRename => ("Post-rename hook", "post_rename"),
+    };
+
+    let mut env = vec![
+        ("SNAPSR_SNAP", context.snap.to_string()),
+        ("SNAPSR_PHASE", phase.to_string()),
+        (
+            "SNAPSR_MODULES",
+            context
+                .modules
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        (
+            "SNAPSR_STORE_DIR",
+            store::get_objects_dir().display().to_string(),
+        ),
+    ];
+    env.extend(context.env.iter().cloned());
+
+    let mut result = HooksOutcome::Success;
+
+    for (module, stage_hooks) in hooks {
+        // Module hooks only hear about their own module's paths
+        env.retain(|(key, _)| *key != "SNAPSR_MODULE");
+        if let Some(module) = module {
+            env.push(("SNAPSR_MODULE", module.to_string()));
+        }
+        let mut paths: Vec<&Path> = context
+            .items
+            .iter()
+            .filter(|(_, item_module)| module.is_none_or(|module| module == *item_module))
+            .map(|(path, _)| *path)
+            .collect();
+        paths.sort();
+
+        let count = stage_hooks.0.len();
+        for (i, entry) in stage_hooks.0.iter().enumerate() {
+            let mut label = match module {
+                Some(module) => format!("{stage} of {module}"),
+                None => stage.to_string(),
+            };
+            if count > 1 {
+                label += &format!(" ({}/{count})", i + 1);
+            }
+
+            if !matches!(hook_type, HookType::Post) || verbose {
+                log(
+                    logger::LogLevel::Info,
+                    format!("Executing {}", label.to_lowercase()).as_str(),
+                );
+            }
+
+            let (status, output) = run_hook(
+                &entry.run,
+                settings,
+                env.clone(),
+                &paths,
+                &context.log_path,
+                &label,
+            );
+
+            if verbose {
+                print_hook_output(&output);
+            }
+
+            match status {
+                HookStatus::Success => log(
+                    logger::LogLevel::Success,
+                    format!("{label} executed successfully").as_str(),
+                ),
+                HookStatus::Error(reason) => match entry.on_failure {
+                    OnFailure::Ignore => {
+                        if verbose {
+                            log(
+                                logger::LogLevel::Info,
+                                format!("{label} {reason}, ignoring it").as_str(),
+                            );
+                        }
+                    }
+                    OnFailure::Warn => {
+                        log(
+                            logger::LogLevel::Error,
+                            format!(
+                                "{label} {reason}, see {} for its output",
+                                context.log_path.display()
+                            )
+                            .as_str(),
+                        );
+                        result = HooksOutcome::Warned;
+                    }
+                    OnFailure::Abort => {
+                        log(
+                            logger::LogLevel::Error,
+                            format!(
+                                "{label} {reason}, aborting, see {} for its output",
+                                context.log_path.display()
+                            )
+                            .as_str(),
+                        );
+                        return HooksOutcome::Aborted;
+                    }
+                },
+                HookStatus::Nothing => {
+                    log(logger::LogLevel::Warn, format!("{label} is empty").as_str())
+                }
+            }
+        }
+    }
+
+    result
+}
+
+struct RestoreFailure {
+    message: String,
+    /// Whether everything was put back the way it was before the restore
+    rolled_back: bool,
+}
+
+/// Writes every item back to disk and removes the `removed` paths as a
+/// single transaction and returns how many items were restored. If anything
+/// fails, everything already written is rolled back and the error is
+/// returned instead.
+fn restore_items(
+    items: &HashMap<PathBuf, SnapItem>,
+    removed: &BTreeSet<PathBuf>,
+    options: &RestoreOptions,
+    verbose: bool,
+) -> Result<usize, RestoreFailure> {
+    // Sorting by path puts every directory before the items inside it
+    let items: BTreeMap<&PathBuf, &SnapItem> = items.iter().collect();
+
+    let mut transaction = RestoreTransaction {
+        create_parents: options.root.is_some(),
+        replace_dirs: options.force,
+        removed: removed.clone(),
+        ..Default::default()
+    };
+
+    let staged = items.iter().try_for_each(|(src_item, snap_item)| {
+        transaction.stage(src_item, snap_item).map_err(|err| {
+            (
+                format!("Failed to restore item {}", src_item.display()),
+                err,
+            )
+        })
+    });
+
+    let committed = staged
+        .and_then(|_| {
+            transaction.commit().map_err(|(src_item, err)| {
+                (
+                    format!("Failed to restore item {}", src_item.display()),
+                    err,
+                )
+            })
+        })
+        .and_then(|_| {
+            transaction
+                .remove()
+                .map_err(|(path, err)| (format!("Failed to remove {}", path.display()), err))
+        });
+
+    if let Err((message, err)) = committed {
+        let rollback_errors = transaction.rollback();
+        for (path, err) in &rollback_errors {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to roll back {} ({err})", path.display()).as_str(),
+            );
+        }
+
+        return Err(RestoreFailure {
+            message: format!("{message} ({err})"),
+            rolled_back: rollback_errors.is_empty(),
+        });
+    }
+
+    transaction.finish();
+
+    if verbose {
+        for path in removed {
+            log(
+                LogLevel::Success,
+                format!("Removed {}", path.display()).as_str(),
+            );
+        }
+        for src_item in items.keys() {
+            log(
+                LogLevel::Success,
+                format!("Restored {}", src_item.display()).as_str(),
+            );
+        }
+    }
+
+    // Children go first so writing them doesn't bump their parent's mtime
+    for (src_item, snap_item) in items.iter().rev() {
+        let Some(ref attrs) = snap_item.attrs else {
+            continue;
+        };
+        if let Err(err) = attrs.apply(src_item, &snap_item.kind, options) {
+            log(
+                logger::LogLevel::Warn,
+                format!(
+                    "Failed to restore attributes of {} ({err})",
+                    src_item.display()
+                )
+                .as_str(),
+            );
+        }
+    }
+
+    Ok(items.len())
+}
+
+#[derive(Serialize)]
+struct RestorePlan {
+    snap: String,
+    hooks: Vec<PlannedHook>,
+    items: Vec<PlannedItem>,
+}
+
+#[derive(Serialize)]
+struct PlannedHook {
+    stage: &'static str,
+    /// Module the hook belongs to, unset for the snap's global hooks
+    module: Option<String>,
+    command: Vec<String>,
+    on_failure: OnFailure,
+    /// Whether the hook would run without asking first
+    approved: bool,
+}
+
+#[derive(Serialize)]
+struct PlannedItem {
+    path: PathBuf,
+    module: String,
+    action: PlannedAction,
+    /// The parent directory doesn't exist and the snap won't create it, so
+    /// restoring this item would fail
+    missing_parent: bool,
+}
+
+#[derive(Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum PlannedAction {
+    Create,
+    Overwrite,
+    Unchanged,
+}
+
+/// Works out everything a restore would do without touching anything
+fn plan_restore(
+    snap_name: &str,
+    snap_meta: &SnapMetaData,
+    items: &HashMap<PathBuf, SnapItem>,
+    create_parents: bool,
+) -> RestorePlan {
+    let modules: BTreeSet<String> = items.values().map(|item| item.module.clone()).collect();
+    let no_hooks = Hooks::new(None, None);
+    let settings = snap_meta.hooks.as_ref().unwrap_or(&no_hooks);
+    let trusted = TrustedHooks::fetch();
+    let mut hooks = Vec::new();
+    for (stage, hook_type) in [("pre_load", HookType::Pre), ("post_load", HookType::Post)] {
+        for (module, stage_hooks) in snap_meta.hooks_for(hook_type, &modules) {
+            for entry in &stage_hooks.0 {
+                hooks.push(PlannedHook {
+                    stage,
+                    module: module.map(String::from),
+                    command: vec![settings.shell().into(), "-c".into(), entry.run.clone()],
+                    on_failure: entry.on_failure,
+                    approved: trusted
+                        .fingerprints
+                        .contains(&settings.fingerprint(&entry.run)),
+                });
+            }
+        }
+    }
+
+    let sorted_items: BTreeMap<&PathBuf, &SnapItem> = items.iter().collect();
+    let planned_items = sorted_items
+        .into_iter()
+        .map(|(path, item)| {
+            let action = match compare_with_live(path, item) {
+                None => PlannedAction::Unchanged,
+                Some(ItemChange::Removed) => PlannedAction::Create,
+                Some(_) => PlannedAction::Overwrite,
+            };
+
+            let missing_parent = !create_parents
+                && path.parent().is_some_and(|parent| {
+                    !parent.exists()
+                        && !items
+                            .get(parent)
+                            .is_some_and(|parent_item| parent_item.kind == ItemKind::Dir)
+                });
+
+            PlannedItem {
+                path: path.clone(),
+                module: item.module.clone(),
+                action,
+                missing_parent,
+            }
+        })
+        .collect();
+
+    RestorePlan {
+        snap: snap_name.into(),
+        hooks,
+        items: planned_items,
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn print_restore_plan(plan: &RestorePlan) {
+    log(
+        logger::LogLevel::Info,
+        format!(
+            "Dry run of restoring {}, nothing will be changed",
+            plan.snap
+        )
+        .as_str(),
+    );
+
+    let print_hooks = |stage: &str| {
+        for hook in plan.hooks.iter().filter(|hook| hook.stage == stage) {
+            let command = hook
+                .command
+                .iter()
+                .map(|arg| shell_quote(arg))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let stage = match hook.module {
+                Some(ref module) => format!("{} of {module}", hook.stage),
+                None => hook.stage.to_string(),
+            };
+            let approval = if hook.approved {
+                ""
+            } else {
+                " (needs approval)"
+            };
+            log(
+                logger::LogLevel::Info,
+                format!("Would run {stage}{approval}: {command}").as_str(),
+            );
+        }
+    };
+
+    print_hooks("pre_load");
+
+    let (mut create, mut overwrite, mut unchanged, mut missing_parent) = (0, 0, 0, 0);
+    for item in &plan.items {
+        match item.action {
+            PlannedAction::Create => {
+                create += 1;
+                println!("    \x1b[92m+\x1b[0m {} (create)", item.path.display());
+            }
+            PlannedAction::Overwrite => {
+                overwrite += 1;
+                println!("    \x1b[93m~\x1b[0m {} (overwrite)", item.path.display());
+            }
+            PlannedAction::Unchanged => {
+                unchanged += 1;
+                println!("    = {} (unchanged)", item.path.display());
+            }
+        }
+
+        if item.missing_parent {
+            missing_parent += 1;
+            println!(
+                "    \x1b[91m!\x1b[0m {} (parent directory is missing)",
+                item.path.display()
+            );
+        }
+    }
+
+    print_hooks("post_load");
+
+    log(
+        logger::LogLevel::Info,
+        format!(
+            "{create} to create, {overwrite} to overwrite, {unchanged} unchanged, {missing_parent} with a missing parent directory"
+        )
+        .as_str(),
+    );
+}
+
+/// Keeps only the items matching one of `paths`, which can be plain paths or
+/// globs. Items inside a matched directory are kept as well. Patterns that
+/// match nothing are reported.
+fn filter_items_by_paths(items: &mut HashMap<PathBuf, SnapItem>, paths: &[String]) {
+    let patterns: Vec<glob::Pattern> = paths
+        .iter()
+        .map(|pattern| {
+            let absolute = path::absolute(pattern).unwrap_or_else(|_| PathBuf::from(pattern));
+            glob::Pattern::new(&absolute.to_string_lossy()).unwrap_or_else(|err| {
+                log(
+                    logger::LogLevel::Error,
+                    format!("Invalid path pattern {pattern} ({err})").as_str(),
+                );
+                process::exit(1);
+            })
+        })
+        .collect();
+
+    let mut matched = vec![false; patterns.len()];
+    items.retain(|item_path, _| {
+        let mut keep = false;
+        for (pattern, matched) in patterns.iter().zip(matched.iter_mut()) {
+            if item_path
+                .ancestors()
+                .any(|ancestor| pattern.matches_path(ancestor))
+            {
+                *matched = true;
+                keep = true;
+            }
+        }
+        keep
+    });
+
+    for (pattern, matched) in paths.iter().zip(matched) {
+        if !matched {
+            log(
+                logger::LogLevel::Warn,
+                format!("{pattern} matched nothing in the snap").as_str(),
+            );
+        }
+    }
+}
+
+pub fn cmd_restore_snap(snap_name: String, options: RestoreOptions, verbose: bool) {
+    let snaplog = match SnapLog::fetch() {
+        Some(snaplog) => {
+            if !snaplog.exist(snap_name.as_str()) {
+                log(
+                    logger::LogLevel::Error,
+                    format!("Snap {snap_name} does not exist").as_str(),
+                );
+                return;
+            }
+            snaplog
+        }
+        None => {
+            log(logger::LogLevel::Error, "Failed to read snap log");
+            return;
+        }
+    };
+
+    let snap_dir = get_snaps_dir();
+    let snap_dir = path::Path::new(&snap_dir).join(&snap_name);
+    let snap_config_path = path::Path::new(&snap_dir).join("snap.json");
+
+    let mut snap = SnapMetaData::from(&snap_config_path);
+
+    let hooks_failed;
+    let restored = match snap {
+        Some(ref mut snap_meta) => {
+            let mut resolved_items = match snap_meta.resolve_items(&snaplog) {
+                Ok(items) => items,
+                Err(err) => {
+                    log(
+                        logger::LogLevel::Error,
+                        format!("Failed to resolve {snap_name}'s parent snaps ({err})").as_str(),
+                    );
+                    return;
+                }
+            };
+
+            if !options.modules.is_empty() {
+                let snap_modules: HashSet<&String> =
+                    resolved_items.values().map(|item| &item.module).collect();
+                let unknown: Vec<&str> = options
+                    .modules
+                    .iter()
+                    .filter(|module| !snap_modules.contains(module))
+                    .map(String::as_str)
+                    .collect();
+
+                if !unknown.is_empty() {
+                    log(
+                        logger::LogLevel::Error,
+                        format!(
+                            "Snap {snap_name} has no module(s) named {}",
+                            unknown.join(", ")
+                        )
+                        .as_str(),
+                    );
+                    process::exit(1);
+                }
+
+                resolved_items.retain(|_, item| options.modules.contains(&item.module));
+            }
+
+            if !options.paths.is_empty() {
+                filter_items_by_paths(&mut resolved_items, &options.paths);
+
+                if resolved_items.is_empty() {
+                    log(
+                        logger::LogLevel::Error,
+                        format!("None of the given paths are in {snap_name}").as_str(),
+                    );
+                    process::exit(1);
+                }
+            }
+
+            if let Some(ref root) = options.root {
+                resolved_items = resolved_items
+                    .into_iter()
+                    .map(|(path, item)| (reroot(root, &path), item))
+                    .collect();
+            }
+
+            if options.no_hooks && clear_restore_hooks(snap_meta) && !options.json {
+                log(logger::LogLevel::Info, "Skipping all hooks");
+            }
+
+            // The global hooks were written for the whole snap in its usual
+            // place, so they only run on a partial or re-rooted restore when
+            // asked to. Module hooks still run for the modules restored,
+            // unless the files don't end up in their usual place.
+            let partial =
+                !options.modules.is_empty() || !options.paths.is_empty() || options.root.is_some();
+            let mut skipped_hooks = false;
+            if partial
+                && !options.with_hooks
+                && let Some(ref mut hooks) = snap_meta.hooks
+            {
+                skipped_hooks |= hooks.pre_load.take().is_some();
+                skipped_hooks |= hooks.post_load.take().is_some();
+            }
+            if options.root.is_some() && !options.with_hooks {
+                skipped_hooks |= !snap_meta.module_hooks.is_empty();
+                snap_meta.module_hooks.clear();
+            }
+
+            if skipped_hooks && !options.json {
+                let skipped = if options.root.is_some() {
+                    "hooks for a re-rooted restore"
+                } else {
+                    "global hooks for a partial restore"
+                };
+                log(
+                    logger::LogLevel::Info,
+                    format!("Skipping {skipped}, pass --with-hooks to run them").as_str(),
+                );
+            }
+
+            if options.dry_run {
+                let plan = plan_restore(
+                    &snap_name,
+                    snap_meta,
+                    &resolved_items,
+                    options.root.is_some(),
+                );
+                if options.json {
+                    match serde_json::to_string_pretty(&plan) {
+                        Ok(json_txt) => println!("{json_txt}"),
+                        Err(err) => log(
+                            logger::LogLevel::Error,
+                            format!("Failed to serialize restore plan ({err})").as_str(),
+                        ),
+                    }
+                } else {
+                    print_restore_plan(&plan);
+                }
+                return;
+            }
+
+            let modules: BTreeSet<String> = resolved_items
+                .values()
+                .map(|item| item.module.clone())
+                .collect();
+            if !approve_hooks(&snap_name, snap_meta, &modules) {
+                log(
+                    logger::LogLevel::Error,
+                    format!(
+                        "Not restoring {snap_name}, pass --no-hooks to restore it without its hooks"
+                    )
+                    .as_str(),
+                );
+                process::exit(1);
+            }
+
+            let checkpoint_dir = match create_checkpoint(&snap_name, snap_meta, &resolved_items) {
+                Ok(checkpoint_dir) => checkpoint_dir,
+                Err(err) => {
+                    log(
+                        logger::LogLevel::Error,
+                        format!("Failed to create a checkpoint, aborting restore ({err})").as_str(),
+                    );
+                    return;
+                }
+            };
+
+            let hook_context = HookContext {
+                snap: &snap_name,
+                modules,
+                items: resolved_items
+                    .iter()
+                    .map(|(path, item)| (path.as_path(), item.module.as_str()))
+                    .collect(),
+                log_path: new_hook_log(&format!("restore-{snap_name}")),
+                env: Vec::new(),
+            };
+
+            let pre_load = run_restore_hooks(snap_meta, HookType::Pre, &hook_context, verbose);
+            if pre_load == HooksOutcome::Aborted {
+                // Nothing changed, so there is nothing to undo either
+                let _ = fs::remove_dir_all(&checkpoint_dir);
+                log(
+                    logger::LogLevel::Error,
+                    format!("Aborting restore of {snap_name}, no files were changed").as_str(),
+                );
+                process::exit(1);
+            }
+
+            match restore_items(&resolved_items, &BTreeSet::new(), &options, verbose) {
+                Ok(restored) => {
+                    let post_load =
+                        run_restore_hooks(snap_meta, HookType::Post, &hook_context, verbose);
+                    hooks_failed =
+                        pre_load != HooksOutcome::Success || post_load != HooksOutcome::Success;
+                    restored
+                }
+                Err(failure) => {
+                    log(logger::LogLevel::Error, failure.message.as_str());
+
+                    if failure.rolled_back {
+                        // Nothing changed, so there is nothing to undo either
+                        let _ = fs::remove_dir_all(&checkpoint_dir);
+                        log(
+                            logger::LogLevel::Error,
+                            format!("Rolled back restore of {snap_name}, no files were changed")
+                                .as_str(),
+                        );
+                    } else {
+                        log(
+                            logger::LogLevel::Error,
+                            format!(
+                                "Restore of {snap_name} could not be fully rolled back, use --undo to recover the previous files"
+                            )
+                            .as_str(),
+                        );
+                    }
+                    process::exit(1);
+                }
+            }
+        }
+
+        None => {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to read {snap_name}'s metadata").as_str(),
+            );
+            return;
+        }
+    };
+
+    log(
+        logger::LogLevel::Success,
+        format!("Fully applied {snap_name}, restored {restored} item(s)").as_str(),
+    );
+
+    if hooks_failed {
+        process::exit(1);
+    }
+}
+
+/// Puts back what the most recent restore overwrote, using the checkpoint it
+/// took beforehand, and reruns the restored snap's hooks around it
+pub fn cmd_undo_restore(options: RestoreOptions, verbose: bool) {
+    let checkpoint_dir = match list_checkpoints() {
+        Ok(mut checkpoints) => match checkpoints.pop() {
+            Some(checkpoint_dir) => checkpoint_dir,
+            None => {
+                log(logger::LogLevel::Info, "Nothing to undo");
+                return;
+            }
+        },
+        Err(err) => {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to read checkpoints ({err})").as_str(),
+            );
+            process::exit(1);
+        }
+    };
+
+    let mut checkpoint =
+        SnapMetaData::from(&checkpoint_dir.join("snap.json")).unwrap_or_else(|| {
+            log(
+                logger::LogLevel::Error,
+                format!("Failed to read checkpoint {}", checkpoint_dir.display()).as_str(),
+            );
+            process::exit(1);
+        });
+
+    log(
+        logger::LogLevel::Info,
+        format!(
+            "Undoing restore of {} from {}",
+            checkpoint.restored.as_deref().unwrap_or("unknown snap"),
+            checkpoint.timestamp
+        )
+        .as_str(),
+    );
+
+    let restored_snap = checkpoint.restored.clone().unwrap_or_default();
+    let modules = checkpoint.module_hooks.keys().cloned().collect();
+    if options.no_hooks {
+        clear_restore_hooks(&mut checkpoint);
+    } else if !approve_hooks(&restored_snap, &checkpoint, &modules) {
+        log(
+            logger::LogLevel::Error,
+            "Not undoing, pass --no-hooks to undo without running hooks",
+        );
+        process::exit(1);
+    }
+
+    // Paths the restore created don't belong to any module once removed
+    let hook_context = HookContext {
+        snap: checkpoint.restored.as_deref().unwrap_or_default(),
+        modules: checkpoint.module_hooks.keys().cloned().collect(),
+        items: checkpoint
+            .items
+            .iter()
+            .map(|(path, item)| (path.as_path(), item.module.as_str()))
+            .chain(checkpoint.removed.iter().map(|path| (path.as_path(), "")))
+            .collect(),
+        log_path: new_hook_log("undo"),
+        env: Vec::new(),
+    };
+    let pre_load = run_restore_hooks(&checkpoint, HookType::Pre, &hook_context, verbose);
+    if pre_load == HooksOutcome::Aborted {
+        log(
+            logger::LogLevel::Error,
+            "Aborting the undo, no files were changed",
+        );
+        process::exit(1);
+    }
+
+    let restored = restore_items(&checkpoint.items, &checkpoint.removed, &options, verbose)
+        .unwrap_or_else(|failure| {
+            log(logger::LogLevel::Error, failure.message.as_str());
+            if failure.rolled_back {
+                log(
+                    logger::LogLevel::Error,
+                    "Rolled back the undo, no files were changed",
+                );
+            }
+            log(
+                logger::LogLevel::Warn,
+                "Keeping the checkpoint so the undo can be retried",
+            );
+            process::exit(1);
+        });
+
+    let post_load = run_restore_hooks(&checkpoint, HookType::Post, &hook_context, verbose);
+
+    let total = restored + checkpoint.removed.len();
+    log(
+        logger::LogLevel::Info,
+        format!("Undid {total} item(s)").as_str(),
+    );
+
+    if let Err(err) = fs::remove_dir_all(&checkpoint_dir) {
+        log(
+            logger::LogLevel::Error,
+            format!("Failed to remove checkpoint ({err})").as_str(),
+        );
+        process::exit(1);
+    }
+
+    collect_unreferenced_objects();
+
+    if pre_load != HooksOutcome::Success || post_load != HooksOutcome::Success {
+        process::exit(1);
+    }
+}
+
+pub fn cmd_delete_snap(snap: String, snap_config_path: Option<PathBuf>, verbose: bool) {
+    let mut snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        log(logger::LogLevel::Error, "Failed to read snap log");
+        process::exit(1)
+    });
+
+    if !snaplog.exist(&snap) {
+        log(
+            logger::LogLevel::Error,
+            format!("Snap {snap} does not exist").as_str(),
+        );
+        process::exit(1);
+    }
+
+    match fold_into_children(&snaplog, &snap) {
+        Ok(0) => {}
+        Ok(folded) => log(
+            logger::LogLevel::Info,
+            format!("Folded {snap} into {folded} snap(s) based on it").as_str(),
+        ),
+        Err(err) => {
+            log(
+                logger::LogLevel::Error,
+                format!("Refusing to delete {snap}, snaps based on it can't be updated ({err})")
+                    .as_str(),
+            );
+            process::exit(1);
+        }
+    }
+
+    let snap_dir = snaplog.snaps.remove(&snap).unwrap_or_else(|| {
+        log(
+            logger::LogLevel::Error,
+            format!("Snap {snap} does not exist").as_str(),
+        );
+        process::exit(1);
+    });
+
+    fs::remove_dir_all(snap_dir).unwrap_or_else(|err| {
+        log(
+            logger::LogLevel::Error,
+            format!("Failed to remove snap directory ({err})").as_str(),
+        );
+        process::exit(1);
+    });
+
+    snaplog.save().unwrap_or_else(|_| {
+        log(
+            logger::LogLevel::Error,
+            "Failed to update snap log, please try again",
+        );
+        process::exit(1);
+    });
+
+    log(
+        logger::LogLevel::Success,
+        format!("Deleted {snap}").as_str(),
+    );
+
+    if let Ok(config) = SnapConfig::load(snap_config_path) {
+        let hook_context = HookContext::new(&snap, "delete");
+        if run_config_hooks(&config, HookType::PostDelete, &hook_context, verbose)
+            != HooksOutcome::Success
+        {
+            collect_unreferenced_objects();
+            process::exit(1);
+        }
+    }
+
+    collect_unreferenced_objects();
+}
+
+pub fn cmd_rename_snap(
+    old_name: &str,
+    new_name: &str,
+    snap_config_path: Option<PathBuf>,
     verbose: bool,
 ) {
-    match SnapLog::fetch() {
-        Some(snaplog) => {
-            if snaplog.exist(snap_name.as_str()) {
-                let mut input = String::new();
+    let mut snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        println!("[\x1b[1;91m-\x1b[0m] Failed to read snap log");
+        process::exit(1);
+    });
+
+    if !snaplog.snaps.contains_key(old_name) {
+        eprintln!("[\x1b[1;91m-\x1b[0m] Snap {old_name} does not exist");
+        process::exit(1);
+    }
+
+    let snap_path = snaplog.snaps.get(old_name).unwrap_or_else(|| {
+        eprintln!("[\x1b[1;91m-\x1b[0m] Failed to get snap from snap log");
+        process::exit(1);
+    });
+
+    let new_snap_path = replace_component_in_path(snap_path, new_name, 1).unwrap_or_else(|| {
+        eprintln!("[\x1b[1;91m-\x1b[0m] Failed");
+        process::exit(1);
+    });
+
+    fs::rename(snap_path, &new_snap_path).unwrap_or_else(|_| {
+        eprintln!("[\x1b[1;91m-\x1b[0m] Failed to rename snap directory");
+        process::exit(1);
+    });
+
+    snaplog.snaps.remove(old_name);
+    snaplog.snaps.insert(new_name.into(), new_snap_path);
+
+    snaplog.save().unwrap_or_else(|_| {
+        eprintln!("[\x1b[1;91m-\x1b[0m] Failed to update snaplog no changes made");
+        process::exit(1);
+    });
+
+    for snap_dir in snaplog.snaps.values() {
+        let snap_meta_path = snap_dir.join("snap.json");
+        if let Some(mut snap_meta) = SnapMetaData::from(&snap_meta_path)
+            && snap_meta.parent.as_deref() == Some(old_name)
+        {
+            snap_meta.parent = Some(new_name.into());
+            if snap_meta.save(&snap_meta_path).is_err() {
+                eprintln!(
+                    "[\x1b[1;91m-\x1b[0m] Failed to point {} at the renamed snap",
+                    snap_dir.display()
+                );
+            }
+        }
+    }
+
+    println!("[\x1b[1;92m+\x1b[0m] Renamed snap to {new_name}");
+
+    if let Ok(config) = SnapConfig::load(snap_config_path) {
+        let mut hook_context = HookContext::new(new_name, "rename");
+        hook_context.env.push(("SNAPSR_OLD_SNAP", old_name.into()));
+        if run_config_hooks(&config, HookType::PostRename, &hook_context, verbose)
+            != HooksOutcome::Success
+        {
+            process::exit(1);
+        }
+    }
+}
+
+/// Parses the name of a stage that runs around restoring a snap
+fn parse_load_stage(stage: &str) -> HookType {
+    match stage {
+        "pre_load" => HookType::Pre,
+        "post_load" => HookType::Post,
+        _ => {
+            log(
+                logger::LogLevel::Error,
+                format!("Unknown hook stage {stage}, expected pre_load or post_load").as_str(),
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Makes sure `module` is one of the snap's modules
+fn check_snap_module(snap_name: &str, items: &HashMap<PathBuf, SnapItem>, module: &str) {
+    if !items.values().any(|item| item.module == module) {
+        log(
+            logger::LogLevel::Error,
+            format!("Snap {snap_name} has no module named {module}").as_str(),
+        );
+        process::exit(1);
+    }
+}
+
+/// Saves a snap's edited hooks, trusting only the fingerprints in `trust`
+/// since the rest of its hooks may not have been approved
+fn save_snap_hooks(
+    snaplog: &SnapLog,
+    snap_name: &str,
+    snap_meta: &SnapMetaData,
+    trust: Vec<String>,
+) {
+    let Some(snap_dir) = snaplog.snaps.get(snap_name) else {
+        log(
+            logger::LogLevel::Error,
+            format!("Snap {snap_name} does not exist").as_str(),
+        );
+        process::exit(1);
+    };
+
+    if let Err(err) = snap_meta.save(&snap_dir.join("snap.json")) {
+        log(
+            logger::LogLevel::Error,
+            format!("Failed to update {snap_name}'s metadata ({err})").as_str(),
+        );
+        process::exit(1);
+    }
+
+    if let Err(err) = TrustedHooks::add(trust) {
+        log(
+            logger::LogLevel::Warn,
+            format!("Failed to trust {snap_name}'s new hooks ({err})").as_str(),
+        );
+    }
+}
+
+pub fn cmd_show_hooks(snap_name: &str) {
+    let snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        log(logger::LogLevel::Error, "Failed to read snap log");
+        process::exit(1);
+    });
+    let (snap_meta, _) = fetch_resolved_snap(&snaplog, snap_name);
+
+    let no_hooks = Hooks::new(None, None);
+    let settings = snap_meta.hooks.as_ref().unwrap_or(&no_hooks);
+    let all_hooks = std::iter::once((None, settings)).chain(
+        snap_meta
+            .module_hooks
+            .iter()
+            .map(|(module, hooks)| (Some(module), hooks)),
+    );
+
+    let mut shown = 0;
+    for (module, hooks) in all_hooks {
+        for (stage, hook_type) in [("pre_load", HookType::Pre), ("post_load", HookType::Post)] {
+            let Some(stage_hooks) = hooks.get(hook_type) else {
+                continue;
+            };
+            if shown == 0 {
                 log(
                     logger::LogLevel::Info,
-                    format!("Snap {snap_name} already exist. Do you wish to overwrite (y/N)? ")
-                        .as_str(),
+                    format!("Hooks of {snap_name}").as_str(),
                 );
-                io::stdout().flush().unwrap();
-                io::stdin().read_line(&mut input).unwrap();
-                let input = input.trim();
-                let input = input.to_lowercase();
+            }
+            match module {
+                Some(module) => println!("    {stage} of {module}"),
+                None => println!("    {stage}"),
+            }
+            for line in stage_hooks.describe() {
+                println!("      {line}");
+            }
+            shown += 1;
+        }
+    }
 
-                if input != "y" && input != "yes" {
-                    log(logger::LogLevel::Info, "aborting");
-                    return;
-                }
+    if shown == 0 {
+        log(
+            logger::LogLevel::Info,
+            format!("Snap {snap_name} has no hooks").as_str(),
+        );
+        return;
+    }
+
+    let timeout = match settings.timeout() {
+        Some(timeout) => format!("{}s", timeout.as_secs()),
+        None => String::from("none"),
+    };
+    let cwd = settings
+        .cwd
+        .as_ref()
+        .map_or(String::from("the current directory"), |cwd| {
+            cwd.display().to_string()
+        });
+    println!(
+        "    run with {} in {cwd}, timeout {timeout}",
+        settings.shell()
+    );
+}
+
+/// Replaces one stage of a snap's hooks with a single command, or clears it
+/// when `command` is unset. `module` picks a module's hooks over the global ones.
+pub fn cmd_set_hook(snap_name: &str, stage: &str, command: Option<String>, module: Option<&str>) {
+    let hook_type = parse_load_stage(stage);
+    let snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        log(logger::LogLevel::Error, "Failed to read snap log");
+        process::exit(1);
+    });
+    let (mut snap_meta, items) = fetch_resolved_snap(&snaplog, snap_name);
+
+    // The user typed this command, so it is trusted as a local snap's would be
+    let no_hooks = Hooks::new(None, None);
+    let settings = snap_meta.hooks.as_ref().unwrap_or(&no_hooks);
+    let trust: Vec<String> = command
+        .iter()
+        .map(|command| settings.fingerprint(command))
+        .collect();
+
+    let hooks = match module {
+        Some(module) => {
+            check_snap_module(snap_name, &items, module);
+            snap_meta
+                .module_hooks
+                .entry(module.to_string())
+                .or_insert_with(|| Hooks::new(None, None))
+        }
+        None => snap_meta
+            .hooks
+            .get_or_insert_with(|| Hooks::new(None, None)),
+    };
+
+    let target = match module {
+        Some(module) => format!("{stage} of {snap_name}'s {module} module"),
+        None => format!("{stage} of {snap_name}"),
+    };
+
+    let cleared = command.is_none();
+    let previous = std::mem::replace(hooks.get_mut(hook_type), command.map(HookStage::from));
+    if cleared && previous.is_none() {
+        log(
+            logger::LogLevel::Info,
+            format!("{target} is not set, nothing to clear").as_str(),
+        );
+        return;
+    }
+
+    snap_meta.module_hooks.retain(|_, hooks| !hooks.is_empty());
+    save_snap_hooks(&snaplog, snap_name, &snap_meta, trust);
+
+    if cleared {
+        log(
+            logger::LogLevel::Success,
+            format!("Cleared {target}").as_str(),
+        );
+    } else {
+        log(logger::LogLevel::Success, format!("Set {target}").as_str());
+    }
+}
 
-                if let Some(log_entry) = snaplog.snaps.get(&snap_name) {
-                    fs::remove_dir_all(log_entry).unwrap_or_else(|err| {
-                        log(
-                            logger::LogLevel::Error,
-                            format!("Failed to clear existing snap ({err})").as_str(),
-                        );
-                        process::exit(1);
-                    });
-                }
-            }
-        }
-        None => {
-            log(logger::LogLevel::Error, "Failed to read snap log");
-            return;
+/// Runs one stage of a snap's hooks the way a restore would, without touching
+/// any files
+pub fn cmd_test_hook(snap_name: &str, stage: &str, module: Option<&str>) {
+    let hook_type = parse_load_stage(stage);
+    let snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        log(logger::LogLevel::Error, "Failed to read snap log");
+        process::exit(1);
+    });
+    let (mut snap_meta, mut items) = fetch_resolved_snap(&snaplog, snap_name);
+
+    if let Some(module) = module {
+        check_snap_module(snap_name, &items, module);
+        items.retain(|_, item| item.module == module);
+        if let Some(ref mut hooks) = snap_meta.hooks {
+            hooks.pre_load = None;
+            hooks.post_load = None;
         }
     }
 
-    let snap = match snap_config_path {
-        Some(path) => {
-            let path = path::PathBuf::from(&path);
-            SnapConfig::from(path)
-        }
+    let modules: BTreeSet<String> = items.values().map(|item| item.module.clone()).collect();
+    if snap_meta.hooks_for(hook_type, &modules).is_empty() {
+        log(
+            logger::LogLevel::Info,
+            format!("Snap {snap_name} has no {stage} hooks to run").as_str(),
+        );
+        return;
+    }
 
-        None => {
-            let path = get_snap_config_dir();
-            let path = path::Path::new(&path).join("config.toml");
-            SnapConfig::from(path)
-        }
-    };
+    if !approve_hooks(snap_name, &snap_meta, &modules) {
+        log(logger::LogLevel::Error, "Not running the hooks");
+        process::exit(1);
+    }
 
-    let snap = match snap {
-        Some(config) => config,
-        None => {
-            log(logger::LogLevel::Error, "Failed to read snap config");
-            return;
-        }
+    let hook_context = HookContext {
+        snap: snap_name,
+        modules,
+        items: items
+            .iter()
+            .map(|(path, item)| (path.as_path(), item.module.as_str()))
+            .collect(),
+        log_path: new_hook_log(&format!("test-{snap_name}")),
+        env: Vec::new(),
     };
 
-    let snap_dir = get_snaps_dir();
-    let snap_dir = path::Path::new(&snap_dir).join(&snap_name);
+    // The hooks' output is what a test run is for, so it is always shown
+    if run_restore_hooks(&snap_meta, hook_type, &hook_context, true) != HooksOutcome::Success {
+        process::exit(1);
+    }
+}
 
-    if let Err(_) = fs::create_dir_all(&snap_dir) {
-        log(logger::LogLevel::Error, "Failed to create snap directory");
+/// Takes a snap's hooks from the current config again, leaving its files be
+pub fn cmd_refresh_hooks(snap_name: &str, snap_config_path: Option<PathBuf>) {
+    let snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        log(logger::LogLevel::Error, "Failed to read snap log");
+        process::exit(1);
+    });
+    let (mut snap_meta, _) = fetch_resolved_snap(&snaplog, snap_name);
+
+    let config = SnapConfig::load(snap_config_path).unwrap_or_else(|err| {
+        log(
+            logger::LogLevel::Error,
+            format!("Failed to read snap config ({err})").as_str(),
+        );
+        process::exit(1);
+    });
+
+    let hooks = config.hooks.as_ref().map(Hooks::for_snap);
+    let module_hooks = config.module_hooks();
+    if snap_meta.hooks == hooks && snap_meta.module_hooks == module_hooks {
+        log(
+            logger::LogLevel::Info,
+            format!("{snap_name}'s hooks already match the config").as_str(),
+        );
         return;
     }
 
-    let mut items_src_to_dst: HashMap<PathBuf, PathBuf> = HashMap::new();
-    let mut size_of_snap = 0;
-    let mut total_items = 0;
-    let mut snapped_items_amount = 0;
+    snap_meta.hooks = hooks;
+    snap_meta.module_hooks = module_hooks;
+    // Every hook now comes from the local config
+    let trust = snap_meta.hook_fingerprints();
+    save_snap_hooks(&snaplog, snap_name, &snap_meta, trust);
 
-    for (module_name, module) in &snap.modules {
-        let module_dir = snap_dir.join(&module_name);
+    log(
+        logger::LogLevel::Success,
+        format!("Refreshed {snap_name}'s hooks from the config").as_str(),
+    );
+}
 
-        if let Err(_) = fs::create_dir_all(&module_dir) {
+/// Checks that every hook of a stage at least parses
+fn check_stage_syntax(settings: &Hooks, stage: &str, stage_hooks: &HookStage) -> usize {
+    let mut errors = 0;
+    for entry in &stage_hooks.0 {
+        if let Err(err) = hook::check_syntax(settings.shell(), &entry.run) {
             log(
                 logger::LogLevel::Error,
-                format!("Failed to create module directory for {module_name}").as_str(),
+                format!("{stage} hook `{}` doesn't parse ({err})", entry.run).as_str(),
             );
-            continue;
+            errors += 1;
         }
+    }
+    errors
+}
 
-        let items = module.get_item_paths();
-        total_items += items.len();
+/// Reports everything wrong with a config without snapping anything
+pub fn cmd_check_config(snap_config_path: Option<PathBuf>) {
+    let config_path = SnapConfig::resolve_path(snap_config_path);
+    log(
+        logger::LogLevel::Info,
+        format!("Checking {}", config_path.display()).as_str(),
+    );
+
+    let config = SnapConfig::read(&config_path).unwrap_or_else(|err| {
+        log(logger::LogLevel::Error, err.as_str());
+        process::exit(1);
+    });
+
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    let mut exclude_patterns = Vec::new();
+    let modules: BTreeMap<&String, &ModuleConfig> = config.modules.iter().collect();
+    let all_excludes =
+        config
+            .exclude
+            .iter()
+            .map(|pattern| (None, pattern))
+            .chain(modules.iter().flat_map(|(module_name, module)| {
+                module
+                    .exclude
+                    .iter()
+                    .map(|pattern| (Some(module_name.as_str()), pattern))
+            }));
+    for (module_name, pattern) in all_excludes {
+        match glob::Pattern::new(pattern) {
+            Ok(pattern) => exclude_patterns.push((module_name, pattern)),
+            Err(err) => {
+                let owner = module_name.map_or(String::new(), |name| format!(" of {name}"));
+                log(
+                    logger::LogLevel::Error,
+                    format!("Exclude pattern {pattern}{owner} is not a valid glob ({err})")
+                        .as_str(),
+                );
+                errors += 1;
+            }
+        }
+    }
+
+    if modules.is_empty() {
+        log(logger::LogLevel::Warn, "The config has no modules");
+        warnings += 1;
+    }
 
+    for (module_name, module) in &modules {
         log(
             logger::LogLevel::Info,
-            format!("{module_name}: {} items", items.len()).as_str(),
+            format!("Module {module_name}").as_str(),
         );
 
-        for item in items {
-            if let (Some(parent), Some(file_child)) = (item.parent(), item.file_name()) {
-                if let Some(grandparent) = parent.file_name() {
-                    let grandparent_key = grandparent.to_string_lossy();
-                    let file_child_key = file_child.to_string_lossy();
+        let rules = ExcludeRules {
+            patterns: exclude_patterns
+                .iter()
+                .filter(|(owner, _)| owner.is_none_or(|owner| owner == module_name.as_str()))
+                .map(|(_, pattern)| pattern.clone())
+                .collect(),
+            root: None,
+        };
 
-                    let file_key = grandparent_key.to_string() + "_" + &file_child_key;
-                    let saved_item_path = module_dir.join(file_key);
+        for pattern in &module.include {
+            let mut items = BTreeMap::new();
+            let mut visited = HashSet::new();
+            let mut excluded = Vec::new();
 
-                    if let Ok(size) = fs::copy(&item, &saved_item_path) {
-                        if verbose {
-                            log(
-                                logger::LogLevel::Success,
-                                format!("Snapped {} ({module_name})", item.display()).as_str(),
-                            );
-                        }
-                        items_src_to_dst.insert(item, saved_item_path);
-                        size_of_snap += size;
-                        snapped_items_amount += 1;
-                    } else {
-                        log(
-                            logger::LogLevel::Error,
-                            format!(
-                                "Failed to snap {}, skipping ({module_name})",
-                                item.display()
-                            )
-                            .as_str(),
-                        );
-                    }
+            match module.collect_pattern(pattern, &rules, &mut items, &mut visited, &mut excluded) {
+                Err(err) => {
+                    log(
+                        logger::LogLevel::Error,
+                        format!("{pattern} is not a valid glob ({err})").as_str(),
+                    );
+                    errors += 1;
+                }
+                Ok(()) if items.is_empty() => {
+                    log(
+                        logger::LogLevel::Warn,
+                        format!("{pattern} matches nothing").as_str(),
+                    );
+                    warnings += 1;
                 }
+                Ok(()) => println!("    {pattern}: {} item(s)", items.len()),
+            }
+        }
+    }
+
+    let no_hooks = Hooks::new(None, None);
+    let settings = config.hooks.as_ref().unwrap_or(&no_hooks);
+    for (stage, hook_type) in [
+        ("pre_load", HookType::Pre),
+        ("post_load", HookType::Post),
+        ("pre_snap", HookType::PreSnap),
+        ("post_snap", HookType::PostSnap),
+        ("post_delete", HookType::PostDelete),
+        ("post_rename", HookType::PostRename),
+    ] {
+        if let Some(stage_hooks) = settings.get(hook_type) {
+            errors += check_stage_syntax(settings, stage, stage_hooks);
+        }
+    }
+    for (module_name, module) in &modules {
+        for (stage, stage_hooks) in [
+            ("pre_load", &module.pre_load),
+            ("post_load", &module.post_load),
+        ] {
+            if let Some(stage_hooks) = stage_hooks {
+                let stage = format!("{stage} of {module_name}");
+                errors += check_stage_syntax(settings, &stage, stage_hooks);
             }
         }
     }
 
-    let hooks: Option<Hooks>;
-    if pre_hook.is_some() || post_hook.is_some() {
-        hooks = Some(Hooks::new(pre_hook, post_hook))
+    if errors > 0 {
+        log(
+            logger::LogLevel::Error,
+            format!("Found {errors} error(s) and {warnings} warning(s)").as_str(),
+        );
+        process::exit(1);
+    } else if warnings > 0 {
+        log(
+            logger::LogLevel::Warn,
+            format!("Config is usable, with {warnings} warning(s)").as_str(),
+        );
     } else {
-        hooks = snap.hooks;
+        log(logger::LogLevel::Success, "Config is valid");
     }
+}
 
-    let snap_meta_data = SnapMetaData::new(items_src_to_dst, hooks, size_of_snap);
-    if let Ok(_) = snap_meta_data.save(&snap_dir.join("snap.json")) {
-        if let Some(mut snaplog) = SnapLog::fetch() {
-            snaplog.snaps.insert(snap_name, snap_dir);
-            if let Ok(_) = snaplog.save() {
-                log(
-                    logger::LogLevel::Success,
-                    format!("Saved Snap {snapped_items_amount}/{total_items} item(s)").as_str(),
-                )
-            } else {
-                log(
-                    logger::LogLevel::Error,
-                    "Failed to save snap log, this snap will be unusable",
-                );
-            }
-        } else {
+/// Writes the snap's metadata and every object it references into a gzipped
+/// tarball laid out as `<snap>/snap.json` and `<snap>/objects/<object>`
+fn write_snap_archive(
+    snap_name: &str,
+    snap_meta: &SnapMetaData,
+    archive_path: &Path,
+) -> io::Result<usize> {
+    let file = fs::File::create(archive_path)?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, GzLevel::default()));
+    let archive_root = PathBuf::from(snap_name);
+
+    let json_data = serde_json::to_string(snap_meta)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json_data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(snap_meta.timestamp.timestamp() as u64);
+    header.set_cksum();
+    archive.append_data(
+        &mut header,
+        archive_root.join("snap.json"),
+        json_data.as_bytes(),
+    )?;
+
+    let objects: HashSet<PathBuf> = snap_meta
+        .items
+        .values()
+        .filter_map(|item| {
+            item.object
+                .as_ref()
+                .and_then(|hash| store::object_path(hash, item.compression).ok())
+        })
+        .collect();
+
+    let objects_dir = store::get_objects_dir();
+    for object in &objects {
+        let relative = object
+            .strip_prefix(&objects_dir)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        archive.append_path_with_name(object, archive_root.join("objects").join(relative))?;
+    }
+
+    archive.into_inner()?.finish()?;
+    Ok(objects.len())
+}
+
+/// Objects read out of an archive into a staging directory, each with the
+/// hash and compression it goes into the store under
+type StagedObjects = Vec<(String, Compression, PathBuf)>;
+
+/// Reads an archive made by [`write_snap_archive`], staging the objects the
+/// local store doesn't have yet in `staging_dir`. Returns the snap name the
+/// archive was exported under along with its metadata and staged objects.
+fn read_snap_archive(
+    archive_path: &Path,
+    staging_dir: &Path,
+) -> io::Result<(String, SnapMetaData, StagedObjects)> {
+    let file = fs::File::open(archive_path)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut snap_name = None;
+    let mut snap_meta = None;
+    let mut staged = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        let mut components = entry_path.components();
+
+        let Some(Component::Normal(root)) = components.next() else {
+            continue;
+        };
+        snap_name.get_or_insert_with(|| root.to_string_lossy().into_owned());
+
+        let relative = components.as_path();
+        if relative == Path::new("snap.json") {
+            let mut json_data = String::new();
+            entry.read_to_string(&mut json_data)?;
+            let data = serde_json::from_str(&json_data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+            snap_meta = Some(data);
+        } else if let Ok(object) = relative.strip_prefix("objects")
+            && let Some((hash, compression)) = store::parse_object_path(object)
+            && let Some(path) = store::stage_object(staging_dir, &hash, compression, &mut entry)?
+        {
+            staged.push((hash, compression, path));
+        }
+    }
+
+    match (snap_name, snap_meta) {
+        (Some(snap_name), Some(snap_meta)) => Ok((snap_name, snap_meta, staged)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "archive does not contain a snap",
+        )),
+    }
+}
+
+/// Snap names become directory names under the snaps directory, so they
+/// can't be empty, contain a slash or refer to a directory above it
+fn check_snap_name(snap_name: &str) -> Result<(), String> {
+    if snap_name.is_empty() || snap_name == "." || snap_name == ".." || snap_name.contains('/') {
+        return Err(format!("{snap_name:?} is not a valid snap name"));
+    }
+    Ok(())
+}
+
+pub fn cmd_export_snap(snap_name: &str, archive_path: &Path) {
+    let snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        log(logger::LogLevel::Error, "Failed to read snap log");
+        process::exit(1);
+    });
+
+    let snap_dir = snaplog.snaps.get(snap_name).unwrap_or_else(|| {
+        log(
+            logger::LogLevel::Error,
+            format!("Snap {snap_name} does not exist").as_str(),
+        );
+        process::exit(1);
+    });
+
+    let mut snap_meta = SnapMetaData::from(&snap_dir.join("snap.json")).unwrap_or_else(|| {
+        log(
+            logger::LogLevel::Error,
+            format!("Failed to read {snap_name}'s metadata").as_str(),
+        );
+        process::exit(1);
+    });
+
+    // Parents won't exist on the importing side, so the archive gets every item
+    snap_meta.items = snap_meta.resolve_items(&snaplog).unwrap_or_else(|err| {
+        log(
+            logger::LogLevel::Error,
+            format!("Failed to resolve {snap_name}'s parent snaps ({err})").as_str(),
+        );
+        process::exit(1);
+    });
+    snap_meta.parent = None;
+    snap_meta.removed.clear();
+
+    match write_snap_archive(snap_name, &snap_meta, archive_path) {
+        Ok(objects) => log(
+            logger::LogLevel::Success,
+            format!(
+                "Exported {snap_name} to {} ({} item(s), {objects} object(s))",
+                archive_path.display(),
+                snap_meta.items.len()
+            )
+            .as_str(),
+        ),
+        Err(err) => {
+            let _ = fs::remove_file(archive_path);
             log(
                 logger::LogLevel::Error,
-                "Failed to save snap log, this snap will be unusable",
+                format!("Failed to export {snap_name} ({err})").as_str(),
             );
+            process::exit(1);
         }
-    } else {
+    }
+}
+
+pub fn cmd_import_snap(archive_path: &Path, snap_name: Option<String>) {
+    if let Some(Err(err)) = snap_name.as_deref().map(check_snap_name) {
+        log(logger::LogLevel::Error, err.as_str());
+        process::exit(1);
+    }
+
+    let staging_dir =
+        PathBuf::from(get_snap_config_dir()).join(format!(".import-{}", process::id()));
+    let result = fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging_dir)
+        .map_err(|err| format!("Failed to create staging directory ({err})"))
+        .and_then(|_| import_snap(archive_path, snap_name, &staging_dir));
+    let _ = fs::remove_dir_all(&staging_dir);
+
+    match result {
+        Ok((snap_name, items)) => log(
+            logger::LogLevel::Success,
+            format!("Imported {snap_name} ({items} item(s))").as_str(),
+        ),
+        Err(err) => {
+            log(logger::LogLevel::Error, err.as_str());
+            process::exit(1);
+        }
+    }
+}
+
+/// Imports the snap in `archive_path`, moving its objects from `staging_dir`
+/// into the store only once it's known the snap can be registered. Returns
+/// the name it was imported under and how many items it has.
+fn import_snap(
+    archive_path: &Path,
+    snap_name: Option<String>,
+    staging_dir: &Path,
+) -> Result<(String, usize), String> {
+    let _store_lock = store::StoreLock::shared()
+        .map_err(|err| format!("Failed to lock the object store ({err})"))?;
+
+    let mut snaplog = SnapLog::fetch().ok_or_else(|| String::from("Failed to read snap log"))?;
+
+    let (archived_name, mut snap_meta, staged) = read_snap_archive(archive_path, staging_dir)
+        .map_err(|err| format!("Failed to read archive {} ({err})", archive_path.display()))?;
+
+    let snap_name = snap_name.unwrap_or(archived_name);
+    check_snap_name(&snap_name)?;
+
+    if snaplog.exist(&snap_name) {
+        return Err(format!(
+            "Snap {snap_name} already exist, import it under a different name"
+        ));
+    }
+
+    if let Some(path) = snap_meta.items.iter().find_map(|(path, item)| {
+        item.object
+            .as_deref()
+            .is_some_and(|hash| !store::is_valid_hash(hash))
+            .then_some(path)
+    }) {
+        return Err(format!(
+            "Archive has an invalid object for {}, refusing to import it",
+            path.display()
+        ));
+    }
+
+    // Exports are flattened, so an archive claiming otherwise can't be trusted
+    snap_meta.parent = None;
+    snap_meta.removed.clear();
+    snap_meta.restored = None;
+
+    let staged_objects: HashSet<PathBuf> = staged
+        .iter()
+        .filter_map(|(hash, compression, _)| store::object_path(hash, *compression).ok())
+        .collect();
+    let missing = snap_meta
+        .items
+        .values()
+        .filter_map(|item| {
+            item.object
+                .as_ref()
+                .and_then(|hash| store::object_path(hash, item.compression).ok())
+        })
+        .filter(|object| !staged_objects.contains(object) && !object.exists())
+        .count();
+
+    if missing > 0 {
+        return Err(format!(
+            "Archive is missing {missing} object(s), refusing to import it"
+        ));
+    }
+
+    for (hash, compression, path) in &staged {
+        store::commit_object(path, hash, *compression)
+            .map_err(|err| format!("Failed to add object {hash} to the store ({err})"))?;
+    }
+
+    let snap_dir = PathBuf::from(get_snaps_dir()).join(&snap_name);
+    fs::create_dir_all(&snap_dir)
+        .map_err(|err| format!("Failed to create snap directory ({err})"))?;
+
+    snap_meta
+        .save(&snap_dir.join("snap.json"))
+        .map_err(|err| format!("Failed to save snap meta data ({err})"))?;
+
+    snaplog.snaps.insert(snap_name.clone(), snap_dir);
+    snaplog
+        .save()
+        .map_err(|_| String::from("Failed to save snap log, this snap will be unusable"))?;
+
+    Ok((snap_name, snap_meta.items.len()))
+}
+
+/// How an item differs between a snap and whatever it is compared against
+enum ItemChange {
+    Added,
+    Removed,
+    Modified(Vec<&'static str>),
+}
+
+/// Maps module names to the changed items in them along with an optional
+/// unified diff of their contents
+type ModuleChanges = BTreeMap<String, Vec<(PathBuf, ItemChange, Option<String>)>>;
+
+/// Compares a snapped item against what is at its path right now, returning
+/// what differs or `None` when they match
+fn compare_with_live(path: &Path, item: &SnapItem) -> Option<ItemChange> {
+    let meta = match item.kind {
+        ItemKind::Symlink(_) => fs::symlink_metadata(path),
+        ItemKind::File | ItemKind::Dir => fs::metadata(path),
+    };
+
+    let Ok(meta) = meta else {
+        return Some(ItemChange::Removed);
+    };
+
+    let mut reasons = Vec::new();
+    match &item.kind {
+        ItemKind::File if !meta.is_file() => reasons.push("type"),
+        ItemKind::Dir if !meta.is_dir() => reasons.push("type"),
+        ItemKind::Symlink(_) if !meta.file_type().is_symlink() => reasons.push("type"),
+        ItemKind::File => {
+            if store::hash_file(path).ok() != item.object {
+                reasons.push("contents");
+            }
+        }
+        ItemKind::Symlink(target) => {
+            if fs::read_link(path).ok().as_ref() != Some(target) {
+                reasons.push("target");
+            }
+        }
+        ItemKind::Dir => {}
+    }
+
+    if !meta.file_type().is_symlink()
+        && let Some(ref attrs) = item.attrs
+        && meta.mode() & 0o7777 != attrs.mode
+    {
+        reasons.push("mode");
+    }
+
+    (!reasons.is_empty()).then_some(ItemChange::Modified(reasons))
+}
+
+/// Compares the same path in two snaps, returning what differs or `None`
+/// when the two items match
+fn compare_items(old: &SnapItem, new: &SnapItem) -> Option<ItemChange> {
+    let mut reasons = Vec::new();
+
+    match (&old.kind, &new.kind) {
+        (ItemKind::File, ItemKind::File) => {
+            if old.object != new.object {
+                reasons.push("contents");
+            }
+        }
+        (ItemKind::Symlink(old_target), ItemKind::Symlink(new_target)) => {
+            if old_target != new_target {
+                reasons.push("target");
+            }
+        }
+        (ItemKind::Dir, ItemKind::Dir) => {}
+        _ => reasons.push("type"),
+    }
+
+    if let (Some(old_attrs), Some(new_attrs)) = (&old.attrs, &new.attrs) {
+        if old_attrs.mode != new_attrs.mode {
+            reasons.push("mode");
+        }
+
+        if old_attrs.uid != new_attrs.uid || old_attrs.gid != new_attrs.gid {
+            reasons.push("owner");
+        }
+    }
+
+    if old.module != new.module {
+        reasons.push("module");
+    }
+
+    (!reasons.is_empty()).then_some(ItemChange::Modified(reasons))
+}
+
+fn fetch_resolved_snap(
+    snaplog: &SnapLog,
+    snap_name: &str,
+) -> (SnapMetaData, HashMap<PathBuf, SnapItem>) {
+    let snap_meta = SnapMetaData::fetch(snaplog, snap_name).unwrap_or_else(|| {
         log(
             logger::LogLevel::Error,
-            "Failed to save snap log, this snap will be unusable",
+            format!("Snap {snap_name} does not exist or its metadata can't be read").as_str(),
         );
-    }
+        process::exit(1);
+    });
+
+    let items = snap_meta.resolve_items(snaplog).unwrap_or_else(|err| {
+        log(
+            logger::LogLevel::Error,
+            format!("Failed to resolve {snap_name}'s parent snaps ({err})").as_str(),
+        );
+        process::exit(1);
+    });
+
+    (snap_meta, items)
 }
 
-pub fn cmd_restore_snap(snap_name: String, verbose: bool) {
-    match SnapLog::fetch() {
-        Some(snaplog) => {
-            if !snaplog.exist(snap_name.as_str()) {
-                log(
-                    logger::LogLevel::Error,
-                    format!("Snap {snap_name} does not exist").as_str(),
-                );
-                return;
-            }
-        }
-        None => {
-            log(logger::LogLevel::Error, "Failed to read snap log");
-            return;
-        }
-    }
+fn read_object(item: &SnapItem) -> Option<Vec<u8>> {
+    let mut contents = Vec::new();
+    store::open_object(item.object.as_ref()?, item.compression)
+        .ok()?
+        .read_to_end(&mut contents)
+        .ok()?;
+    Some(contents)
+}
 
-    let snap_dir = get_snaps_dir();
-    let snap_dir = path::Path::new(&snap_dir).join(&snap_name);
-    let snap_config_path = path::Path::new(&snap_dir).join("snap.json");
+/// Unified diff of two file contents, only when both of them are text
+fn text_diff(
+    old: Option<Vec<u8>>,
+    new: Option<Vec<u8>>,
+    old_label: &str,
+    new_label: &str,
+) -> Option<String> {
+    let (old, new) = (old?, new?);
+    diff::unified_diff(
+        diff::as_text(&old)?,
+        diff::as_text(&new)?,
+        old_label,
+        new_label,
+    )
+}
 
-    let snap = SnapMetaData::from(&snap_config_path);
+/// Prints changes grouped by module and returns how many items were added,
+/// modified and removed
+fn print_changes(changes: &ModuleChanges, removed_label: &str) -> (usize, usize, usize) {
+    let (mut added, mut modified, mut removed) = (0, 0, 0);
 
-    let mut failed = 0;
-    let mut total = 0;
+    for (module_name, items) in changes {
+        log(logger::LogLevel::Info, module_name);
 
-    match snap {
-        Some(ref snap_meta) => {
-            if snap_meta.hook_exist(HookType::Pre) {
-                log(logger::LogLevel::Info, "Executing pre-hook");
-                let status = snap_meta.run_hook(HookType::Pre);
-                match status {
-                    HookStatus::Success => {
-                        log(logger::LogLevel::Success, "Pre-hook executed successfully")
-                    }
-                    HookStatus::Error => log(logger::LogLevel::Error, "Pre-hook failed to execute"),
-                    HookStatus::Nothing => log(logger::LogLevel::Warn, "Pre-hook is empty"),
+        for (path, change, patch) in items {
+            match change {
+                ItemChange::Added => {
+                    added += 1;
+                    println!("    \x1b[92m+\x1b[0m {} (added)", path.display());
                 }
-            }
-
-            for (src_item, dst_item) in &snap_meta.items {
-                total += 1;
-                if let Err(_) = fs::copy(&dst_item, &src_item) {
-                    log(
-                        logger::LogLevel::Error,
-                        format!("Failed to restore item {}", dst_item.display()).as_str(),
-                    );
-                    failed += 1;
-                    continue;
+                ItemChange::Removed => {
+                    removed += 1;
+                    println!("    \x1b[91m-\x1b[0m {} ({removed_label})", path.display());
                 }
-                if verbose {
-                    log(
-                        LogLevel::Success,
-                        format!("Restored {}", dst_item.display()).as_str(),
+                ItemChange::Modified(reasons) => {
+                    modified += 1;
+                    println!(
+                        "    \x1b[93m~\x1b[0m {} ({})",
+                        path.display(),
+                        reasons.join(", ")
                     );
                 }
             }
 
-            if snap_meta.hook_exist(HookType::Post) {
-                if verbose {
-                    log(logger::LogLevel::Success, "Executing post-hook");
-                }
-                let status = snap_meta.run_hook(HookType::Post);
-
-                match status {
-                    HookStatus::Success => {
-                        log(logger::LogLevel::Success, "Post-hook executed successfully")
+            if let Some(patch) = patch {
+                for line in patch.lines() {
+                    match line.chars().next() {
+                        Some('+') => println!("      \x1b[92m{line}\x1b[0m"),
+                        Some('-') => println!("      \x1b[91m{line}\x1b[0m"),
+                        Some('@') => println!("      \x1b[96m{line}\x1b[0m"),
+                        _ => println!("      {line}"),
                     }
-                    HookStatus::Error => {
-                        log(logger::LogLevel::Error, "Post-hook failed to execute")
-                    }
-                    HookStatus::Nothing => log(logger::LogLevel::Warn, "Post-hook is empty"),
                 }
             }
         }
+    }
 
-        None => {
-            log(
-                logger::LogLevel::Error,
-                format!("Failed to read {snap_name}'s metadata").as_str(),
-            );
-            return;
-        }
-    };
-
-    log(
-        logger::LogLevel::Info,
-        format!("Restored {}/{total} item(s)", total - failed).as_str(),
-    );
+    (added, modified, removed)
 }
 
-pub fn cmd_delete_snap(snap: String) {
-    let mut snaplog = SnapLog::fetch().unwrap_or_else(|| {
+pub fn cmd_diff_snap(snap_name: &str, snap_config_path: Option<PathBuf>, patch: bool) {
+    let snaplog = SnapLog::fetch().unwrap_or_else(|| {
         log(logger::LogLevel::Error, "Failed to read snap log");
-        process::exit(1)
+        process::exit(1);
     });
 
-    if !snaplog.exist(&snap) {
-        log(
-            logger::LogLevel::Error,
-            format!("Snap {snap} does not exist").as_str(),
-        );
-        process::exit(1);
+    let (_, items) = fetch_resolved_snap(&snaplog, snap_name);
+
+    let mut changes = ModuleChanges::new();
+
+    for (path, item) in &items {
+        let Some(change) = compare_with_live(path, item) else {
+            continue;
+        };
+
+        let content_changed =
+            matches!(&change, ItemChange::Modified(reasons) if reasons.contains(&"contents"));
+        let item_patch = if patch && content_changed {
+            text_diff(
+                read_object(item),
+                fs::read(path).ok(),
+                format!("{} ({snap_name})", path.display()).as_str(),
+                format!("{} (live)", path.display()).as_str(),
+            )
+        } else {
+            None
+        };
+
+        changes
+            .entry(item.module.clone())
+            .or_default()
+            .push((path.clone(), change, item_patch));
     }
 
-    let snap_dir = snaplog.snaps.remove(&snap).unwrap_or_else(|| {
-        log(
-            logger::LogLevel::Error,
-            format!("Snap {snap} does not exist").as_str(),
-        );
-        process::exit(1);
-    });
+    match SnapConfig::load(snap_config_path) {
+        Ok(config) => {
+            for (module_name, module) in &config.modules {
+                for path in module.get_items(None, &config.exclude).0.into_keys() {
+                    if !items.contains_key(&path) {
+                        changes.entry(module_name.clone()).or_default().push((
+                            path,
+                            ItemChange::Added,
+                            None,
+                        ));
+                    }
+                }
+            }
+        }
+        Err(err) => log(
+            logger::LogLevel::Warn,
+            format!("Failed to read snap config, files not in the snap won't be shown ({err})")
+                .as_str(),
+        ),
+    }
 
-    fs::remove_dir_all(snap_dir).unwrap_or_else(|err| {
+    for module_changes in changes.values_mut() {
+        module_changes.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    let (added, modified, removed) = print_changes(&changes, "missing");
+
+    if added + modified + removed == 0 {
         log(
-            logger::LogLevel::Error,
-            format!("Failed to remove snap directory ({err})").as_str(),
+            logger::LogLevel::Success,
+            format!("Nothing differs from {snap_name}").as_str(),
         );
-        process::exit(1);
-    });
-
-    snaplog.save().unwrap_or_else(|_| {
+    } else {
         log(
-            logger::LogLevel::Error,
-            "Failed to update snap log, please try again",
+            logger::LogLevel::Info,
+            format!("{added} added, {modified} modified, {removed} missing").as_str(),
         );
-        process::exit(1);
-    });
-
-    log(
-        logger::LogLevel::Success,
-        format!("Deleted {snap}").as_str(),
-    )
+    }
 }
 
-pub fn cmd_rename_snap(old_name: &str, new_name: &str) {
-    let mut snaplog = SnapLog::fetch().unwrap_or_else(|| {
-        println!("[\x1b[1;91m-\x1b[0m] Failed to read snap log");
+pub fn cmd_diff_snaps(old_name: &str, new_name: &str, patch: bool) {
+    let snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        log(logger::LogLevel::Error, "Failed to read snap log");
         process::exit(1);
     });
 
-    if !snaplog.snaps.contains_key(old_name) {
-        eprintln!("[\x1b[1;91m-\x1b[0m] Snap {old_name} does not exist");
-        process::exit(1);
-    }
+    let (old_meta, old_items) = fetch_resolved_snap(&snaplog, old_name);
+    let (new_meta, new_items) = fetch_resolved_snap(&snaplog, new_name);
 
-    let snap_path = snaplog.snaps.get(old_name).unwrap_or_else(|| {
-        eprintln!("[\x1b[1;91m-\x1b[0m] Failed to get snap from snap log");
-        process::exit(1);
-    });
+    let mut changes = ModuleChanges::new();
 
-    let new_snap_path = replace_component_in_path(snap_path, new_name, 1).unwrap_or_else(|| {
-        eprintln!("[\x1b[1;91m-\x1b[0m] Failed");
-        process::exit(1);
-    });
+    for (path, old_item) in &old_items {
+        let change = match new_items.get(path) {
+            Some(new_item) => compare_items(old_item, new_item),
+            None => Some(ItemChange::Removed),
+        };
 
-    fs::rename(snap_path, &new_snap_path).unwrap_or_else(|_| {
-        eprintln!("[\x1b[1;91m-\x1b[0m] Failed to rename snap directory");
-        process::exit(1);
-    });
+        let Some(change) = change else {
+            continue;
+        };
 
-    let mut snap_meta = SnapMetaData::from(&new_snap_path.join("snap.json")).unwrap_or_else(|| {
-        eprintln!("[\x1b[1;91m-\x1b[0m] Failed to rename snap directory. DO NOT RUN -c, --clean\nRun the following command to restore snap 'mv {} {}'", new_snap_path.display(), snap_path.display());
-        process::exit(1);
-    });
+        let new_item = new_items.get(path);
+        let content_changed =
+            matches!(&change, ItemChange::Modified(reasons) if reasons.contains(&"contents"));
+        let item_patch = match new_item {
+            Some(new_item) if patch && content_changed => text_diff(
+                read_object(old_item),
+                read_object(new_item),
+                format!("{} ({old_name})", path.display()).as_str(),
+                format!("{} ({new_name})", path.display()).as_str(),
+            ),
+            _ => None,
+        };
 
-    snap_meta.items = snap_meta
-        .items
-        .into_iter()
-        .map(|item| {
-            (
-                item.0,
-                replace_component_in_path(item.1, new_name, 3).unwrap(),
-            )
-        })
+        let module_name = new_item.unwrap_or(old_item).module.clone();
+        changes
+            .entry(module_name)
+            .or_default()
+            .push((path.clone(), change, item_patch));
+    }
+
+    for (path, new_item) in &new_items {
+        if !old_items.contains_key(path) {
+            changes.entry(new_item.module.clone()).or_default().push((
+                path.clone(),
+                ItemChange::Added,
+                None,
+            ));
+        }
+    }
+
+    for module_changes in changes.values_mut() {
+        module_changes.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    let (added, modified, removed) = print_changes(&changes, "removed");
+
+    let no_hooks = Hooks::new(None, None);
+    let mut hook_pairs = vec![(
+        String::new(),
+        old_meta.hooks.as_ref().unwrap_or(&no_hooks),
+        new_meta.hooks.as_ref().unwrap_or(&no_hooks),
+    )];
+    let hook_modules: BTreeSet<&String> = old_meta
+        .module_hooks
+        .keys()
+        .chain(new_meta.module_hooks.keys())
         .collect();
+    for module in hook_modules {
+        hook_pairs.push((
+            format!("{module} "),
+            old_meta.module_hooks.get(module).unwrap_or(&no_hooks),
+            new_meta.module_hooks.get(module).unwrap_or(&no_hooks),
+        ));
+    }
 
-    snap_meta.save(&new_snap_path.join("snap.json")).unwrap_or_else(|_| {
-        eprintln!("[\x1b[1;91m-\x1b[0m] Failed to save snap meta data. DO NOT RUN -c, --clean\nRun the following command to restore snap 'mv {} {}'", new_snap_path.display(), snap_path.display());
-        process::exit(1);
-    });
+    let mut changed_hooks = 0;
+    let hook_changes = hook_pairs
+        .iter()
+        .flat_map(|(prefix, old_hooks, new_hooks)| {
+            [
+                (
+                    format!("{prefix}pre_load"),
+                    &old_hooks.pre_load,
+                    &new_hooks.pre_load,
+                ),
+                (
+                    format!("{prefix}post_load"),
+                    &old_hooks.post_load,
+                    &new_hooks.post_load,
+                ),
+            ]
+        });
 
-    snaplog.snaps.remove(old_name);
-    snaplog.snaps.insert(new_name.into(), new_snap_path);
+    for (stage, old_hook, new_hook) in hook_changes {
+        if old_hook == new_hook {
+            continue;
+        }
 
-    snaplog.save().unwrap_or_else(|_| {
-        eprintln!("[\x1b[1;91m-\x1b[0m] Failed to update snaplog no changes made");
-        process::exit(1);
-    });
+        if changed_hooks == 0 {
+            log(logger::LogLevel::Info, "hooks");
+        }
+        changed_hooks += 1;
 
-    println!("[\x1b[1;92m+\x1b[0m] Renamed snap to {new_name}");
+        println!("    \x1b[93m~\x1b[0m {stage}");
+        for line in old_hook.iter().flat_map(HookStage::describe) {
+            println!("      \x1b[91m-{line}\x1b[0m");
+        }
+        for line in new_hook.iter().flat_map(HookStage::describe) {
+            println!("      \x1b[92m+{line}\x1b[0m");
+        }
+    }
+
+    if added + modified + removed + changed_hooks == 0 {
+        log(
+            logger::LogLevel::Success,
+            format!("{old_name} and {new_name} are identical").as_str(),
+        );
+    } else {
+        log(
+            logger::LogLevel::Info,
+            format!(
+                "{added} added, {modified} modified, {removed} removed, {changed_hooks} hook(s) changed"
+            )
+            .as_str(),
+        );
+    }
 }
 
 pub fn cmd_list_snaps() {
-    let snaps = SnapLog::fetch()
-        .unwrap_or_else(|| {
-            log(logger::LogLevel::Error, "Failed to read snap log");
-            process::exit(1);
-        })
-        .get_snaps_sorted();
+    let snaplog = SnapLog::fetch().unwrap_or_else(|| {
+        log(logger::LogLevel::Error, "Failed to read snap log");
+        process::exit(1);
+    });
+    let snaps = snaplog.get_snaps_sorted();
 
     let headers = vec![
         String::from("Name"),
         String::from("Items"),
-        String::from("Size"),
+        String::from("Size (stored/original)"),
+        String::from("Parent"),
         String::from("Last modified"),
     ];
     let mut rows = Vec::new();
     let mut max_width_name = 4;
-    let mut max_width_size = 5;
+    let mut max_width_size = 22;
     let mut max_width_items = 5;
+    let mut max_width_parent = 6;
 
     for (name, snap_path) in &snaps {
         if let Some(snap_meta) = SnapMetaData::from(&snap_path.join("snap.json")) {
-            let snap_size = snap_meta.size.to_string();
-            let snap_items_amount = snap_meta.items.len().to_string();
+            let snap_size = format!("{}/{}", snap_meta.stored_size, snap_meta.size);
+            let snap_items_amount = snap_meta
+                .resolve_items(&snaplog)
+                .map_or(snap_meta.items.len(), |items| items.len())
+                .to_string();
+            let snap_parent = snap_meta.parent.clone().unwrap_or_default();
 
             let name_len = name.chars().count();
             let size_len = snap_size.chars().count();
@@ -752,10 +4494,13 @@ pub fn cmd_list_snaps() {
                 max_width_items = items_len;
             }
 
+            max_width_parent = max_width_parent.max(snap_parent.chars().count());
+
             rows.push(vec![
                 name.into(),
                 snap_items_amount,
                 snap_size,
+                snap_parent,
                 snap_meta.timestamp.to_string(),
             ]);
         }
@@ -764,7 +4509,13 @@ pub fn cmd_list_snaps() {
     let table = DisplayTable::from(
         headers,
         rows,
-        vec![max_width_name, max_width_items, max_width_size, 36],
+        vec![
+            max_width_name,
+            max_width_items,
+            max_width_size,
+            max_width_parent,
+            36,
+        ],
     );
     table.display();
 }
@@ -822,9 +4573,11 @@ pub fn cmd_clean_snaps() {
         }
     }
 
-    if amount == 0 {
+    let removed_objects = collect_unreferenced_objects();
+
+    if amount == 0 && removed_objects == 0 {
         log(logger::LogLevel::Info, "Nothing to clean");
-    } else {
+    } else if amount > 0 {
         log(
             logger::LogLevel::Success,
             format!("Cleaned {amount} snaps").as_str(),
@@ -847,4 +4600,287 @@ mod test {
             Some(PathBuf::from("/home/bob/.config/waybar/scripts"))
         );
     }
+
+    fn test_item(object: &str) -> SnapItem {
+        SnapItem {
+            module: String::from("hypr"),
+            kind: ItemKind::File,
+            object: Some(object.into()),
+            compression: Compression::None,
+            attrs: Some(ItemAttrs::default()),
+        }
+    }
+
+    #[test]
+    fn test_migrate_legacy_snap() {
+        let txt = r#"{
+            "timestamp": "2024-03-01T10:00:00+01:00",
+            "size": 12,
+            "items": {
+                "/home/bob/.config/hypr/hyprland.conf": "/snaps/old/hypr/home/bob/.config/hypr/hyprland.conf",
+                "/home/bob/.config/waybar/config": "/snaps/old/waybar/waybar_config"
+            },
+            "hooks": {"pre_load": "pkill waybar", "post_load": null}
+        }"#;
+
+        assert!(serde_json::from_str::<SnapMetaData>(txt).is_err());
+        let legacy: LegacySnapMetaData = serde_json::from_str(txt).unwrap();
+        let timestamp = legacy.timestamp;
+
+        let (meta, stored) = legacy.migrate(Path::new("/snaps/old"), |copy| {
+            if copy.ends_with("waybar_config") {
+                Err(io::Error::other("missing"))
+            } else {
+                Ok((String::from("h1"), 12, 10))
+            }
+        });
+
+        assert_eq!(meta.timestamp, timestamp);
+        assert_eq!((meta.size, meta.stored_size), (12, 10));
+        assert_eq!(meta.items.len(), 1);
+        let item = &meta.items[Path::new("/home/bob/.config/hypr/hyprland.conf")];
+        assert_eq!(item.module, "hypr");
+        assert_eq!(item.object.as_deref(), Some("h1"));
+        assert_eq!(item.attrs, None);
+        assert_eq!(
+            stored,
+            [PathBuf::from(
+                "/snaps/old/hypr/home/bob/.config/hypr/hyprland.conf"
+            )]
+        );
+        assert!(meta.hooks.unwrap().pre_load.is_some());
+    }
+
+    #[test]
+    fn test_fold_parent() {
+        let mut parent = SnapMetaData::new(HashMap::new(), None, 0, 0);
+        parent.parent = Some(String::from("base"));
+        parent.items.insert(PathBuf::from("/a"), test_item("a1"));
+        parent.items.insert(PathBuf::from("/b"), test_item("b1"));
+        parent.items.insert(PathBuf::from("/c"), test_item("c1"));
+        parent.removed.insert(PathBuf::from("/d"));
+
+        let mut child = SnapMetaData::new(HashMap::new(), None, 0, 0);
+        child.parent = Some(String::from("parent"));
+        child.items.insert(PathBuf::from("/a"), test_item("a2"));
+        child.removed.insert(PathBuf::from("/b"));
+
+        child.fold_parent(&parent);
+
+        assert_eq!(child.parent.as_deref(), Some("base"));
+        assert_eq!(child.items.get(Path::new("/a")), Some(&test_item("a2")));
+        assert_eq!(child.items.get(Path::new("/b")), None);
+        assert_eq!(child.items.get(Path::new("/c")), Some(&test_item("c1")));
+        assert!(child.removed.contains(Path::new("/b")));
+        assert!(child.removed.contains(Path::new("/d")));
+    }
+
+    #[test]
+    fn test_hooks_for() {
+        let mut snap = SnapMetaData::new(
+            HashMap::new(),
+            Some(Hooks::new(Some("g-pre".into()), Some("g-post".into()))),
+            0,
+            0,
+        );
+        snap.module_hooks.insert(
+            String::from("hypr"),
+            Hooks::new(Some("hypr-pre".into()), None),
+        );
+        snap.module_hooks.insert(
+            String::from("waybar"),
+            Hooks::new(None, Some("waybar-post".into())),
+        );
+
+        let modules = BTreeSet::from([String::from("hypr"), String::from("waybar")]);
+        let hooks = |hook_type| {
+            snap.hooks_for(hook_type, &modules)
+                .into_iter()
+                .flat_map(|(_, stage)| stage.0.iter().map(|entry| entry.run.as_str()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hooks(HookType::Pre), ["g-pre", "hypr-pre"]);
+        assert_eq!(hooks(HookType::Post), ["waybar-post", "g-post"]);
+
+        let only_hypr = BTreeSet::from([String::from("hypr")]);
+        assert_eq!(snap.hooks_for(HookType::Post, &only_hypr).len(), 1);
+    }
+
+    #[test]
+    fn test_text_position() {
+        let txt = "[modules.a]\n  include = 5";
+        assert_eq!(text_position(txt, 0), (1, 1));
+        assert_eq!(text_position(txt, 24), (2, 13));
+    }
+
+    /// Scratch directory removed when the test ends, whether it passes or not
+    struct TestDir(PathBuf);
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_config_includes() {
+        let test_dir =
+            TestDir(std::env::temp_dir().join(format!("snapsr-test-includes-{}", process::id())));
+        let dir = &test_dir.0;
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let write = |name: &str, txt: &str| fs::write(dir.join(name), txt).unwrap();
+
+        write(
+            "config.toml",
+            "include = [\"conf.d/*.toml\"]\n[hooks]\npost_load = \"main\"\n",
+        );
+        write(
+            "conf.d/a.toml",
+            "include = [\"../b.toml\"]\n[modules.a]\ninclude = []\n",
+        );
+        write(
+            "b.toml",
+            "[hooks]\npost_load = \"b\"\n[modules.b]\ninclude = []\n",
+        );
+
+        let config = SnapConfig::read(&dir.join("config.toml")).unwrap();
+        assert!(config.modules.contains_key("a") && config.modules.contains_key("b"));
+        let post_load = config.hooks.unwrap().post_load.unwrap();
+        let commands: Vec<&str> = post_load.0.iter().map(|entry| entry.run.as_str()).collect();
+        assert_eq!(commands, ["main", "b"]);
+
+        write("b.toml", "[modules.a]\ninclude = []\n");
+        let err = SnapConfig::read(&dir.join("config.toml")).unwrap_err();
+        assert!(err.starts_with("Module a is defined in both"));
+
+        write("b.toml", "include = [\"config.toml\"]\n");
+        let err = SnapConfig::read(&dir.join("config.toml")).unwrap_err();
+        assert!(err.starts_with("Recursive include"));
+
+        write("b.toml", "include = [\"missing.toml\"]\n");
+        let err = SnapConfig::read(&dir.join("config.toml")).unwrap_err();
+        assert!(err.starts_with("Included file"));
+
+        write(
+            "b.toml",
+            "[hooks]\npost_load = \"\"\"\ntemplate engine --reload\n\"\"\"\n",
+        );
+        assert!(SnapConfig::read(&dir.join("config.toml")).is_ok());
+
+        write("b.toml", "template hypr.toml\n");
+        let err = SnapConfig::read(&dir.join("config.toml")).unwrap_err();
+        assert!(err.contains("line 1: template lines are no longer supported"));
+    }
+
+    #[test]
+    fn test_restore_transaction_rollback() {
+        let test_dir =
+            TestDir(std::env::temp_dir().join(format!("snapsr-test-rollback-{}", process::id())));
+        let dir = &test_dir.0;
+        fs::create_dir_all(dir).unwrap();
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        let link = |target: &str| SnapItem {
+            module: String::from("m"),
+            kind: ItemKind::Symlink(PathBuf::from(target)),
+            object: None,
+            compression: Compression::None,
+            attrs: None,
+        };
+        let assert_untouched = || {
+            assert_eq!(fs::read_to_string(&a).unwrap(), "old a");
+            assert_eq!(fs::read_to_string(&b).unwrap(), "old b");
+            assert_eq!(fs::read_dir(dir).unwrap().count(), 2);
+        };
+        fs::write(&a, "old a").unwrap();
+        fs::write(&b, "old b").unwrap();
+
+        // a gets swapped in, then renaming b into place fails
+        let mut transaction = RestoreTransaction::default();
+        transaction.stage(&a, &link("new a")).unwrap();
+        transaction.stage(&b, &link("new b")).unwrap();
+        fs::remove_file(sibling_path(&b, "new")).unwrap();
+        let (failed, _) = transaction.commit().unwrap_err();
+        assert_eq!(failed, b);
+        assert!(a.is_symlink());
+        assert!(transaction.rollback().is_empty());
+        assert_untouched();
+
+        // b has no stored copy, so staging fails after a was staged
+        let mut transaction = RestoreTransaction::default();
+        transaction.stage(&a, &link("new a")).unwrap();
+        let no_copy = SnapItem {
+            kind: ItemKind::File,
+            ..link("")
+        };
+        assert!(transaction.stage(&b, &no_copy).is_err());
+        assert!(transaction.rollback().is_empty());
+        assert_untouched();
+    }
+
+    #[test]
+    fn test_resolve_in_root() {
+        let test_dir =
+            TestDir(std::env::temp_dir().join(format!("snapsr-test-root-{}", process::id())));
+        let root = &test_dir.0;
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::create_dir_all(root.join("home/user")).unwrap();
+        fs::write(root.join("etc/hostname"), "backup").unwrap();
+        let link = |target: &str, name: &str| {
+            std::os::unix::fs::symlink(target, root.join(name)).unwrap();
+            root.join(name)
+        };
+
+        let absolute = link("/etc/hostname", "home/user/absolute");
+        let climbing = link("../../../../etc", "home/user/climbing");
+        let relative = link("../../etc", "home/user/relative");
+        let looping = link("/home/user/looping", "home/user/looping");
+
+        let hostname = root.join("etc/hostname");
+        assert_eq!(
+            resolve_in_root(root, &absolute, true),
+            Some(hostname.clone())
+        );
+        assert_eq!(resolve_in_root(root, &absolute, false), Some(absolute));
+        assert_eq!(
+            resolve_in_root(root, &climbing.join("hostname"), false),
+            Some(hostname.clone())
+        );
+        assert_eq!(
+            resolve_in_root(root, &relative, true),
+            Some(root.join("etc"))
+        );
+        assert_eq!(resolve_in_root(root, &looping, true), None);
+        assert_eq!(
+            resolve_in_root(root, Path::new("/etc/hostname"), true),
+            None
+        );
+
+        let module: ModuleConfig =
+            toml::from_str("include = [\"/home/user/absolute\"]\nsymlinks = \"follow\"").unwrap();
+        let (items, _) = module.get_items(Some(root), &[]);
+        assert!(matches!(
+            items.get(&root.join("home/user/absolute")),
+            Some((ItemKind::File, source)) if *source == hostname
+        ));
+    }
+
+    #[test]
+    fn test_hook_stage_forms() {
+        let hooks: Hooks = toml::from_str(
+            r#"
+            pre_load = "echo one"
+            post_load = ["echo two", { run = "echo three", on_failure = "abort" }]
+            "#,
+        )
+        .unwrap();
+
+        let pre_load = hooks.pre_load.unwrap();
+        assert_eq!(pre_load.0.len(), 1);
+        assert_eq!(pre_load.0[0].run, "echo one");
+        assert_eq!(pre_load.0[0].on_failure, OnFailure::Warn);
+
+        let post_load = hooks.post_load.unwrap();
+        assert_eq!(post_load.0[1].run, "echo three");
+        assert_eq!(post_load.0[1].on_failure, OnFailure::Abort);
+    }
 }
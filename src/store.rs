@@ -0,0 +1,362 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+use flate2::Compression as GzLevel;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/// Directory holding the content-addressed objects shared by every snap
+pub fn get_objects_dir() -> PathBuf {
+    let user_home_dir = std::env::var("HOME").expect("Failed to read HOME env variable");
+    PathBuf::from(user_home_dir).join(".config/snapsr/objects")
+}
+
+/// Creates the objects directory if needed and keeps it private, since the
+/// objects hold the contents of everything that was snapped
+pub fn create_objects_dir() -> io::Result<PathBuf> {
+    let objects_dir = get_objects_dir();
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&objects_dir)?;
+    fs::set_permissions(&objects_dir, fs::Permissions::from_mode(0o700))?;
+    Ok(objects_dir)
+}
+
+/// Creates a file only its owner can read, replacing any leftover at `path`
+pub fn create_private_file(path: &Path) -> io::Result<File> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Temporary files younger than this may still be written by a running snap
+const TMP_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Lock on the object store, released when dropped. Adding objects holds it
+/// shared until the snap that references them is registered, while garbage
+/// collection holds it exclusively so it never sees objects that are still
+/// about to be referenced.
+pub struct StoreLock {
+    _file: File,
+}
+
+impl StoreLock {
+    pub fn shared() -> io::Result<Self> {
+        Self::acquire(libc::LOCK_SH)
+    }
+
+    pub fn exclusive() -> io::Result<Self> {
+        Self::acquire(libc::LOCK_EX)
+    }
+
+    fn acquire(operation: libc::c_int) -> io::Result<Self> {
+        let objects_dir = create_objects_dir()?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(objects_dir.with_file_name("objects.lock"))?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+/// Objects are fanned out by the first two hex digits of their hash so no
+/// single directory ends up with thousands of entries. The hash is always of
+/// the uncompressed contents, compressed objects just get an extension.
+/// Anything that isn't a hash is refused, since snaps from elsewhere could
+/// otherwise point outside the store.
+pub fn object_path(hash: &str, compression: Compression) -> io::Result<PathBuf> {
+    if !is_valid_hash(hash) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid object hash {hash}"),
+        ));
+    }
+
+    let (fanout, rest) = hash.split_at(2);
+    let name = match compression {
+        Compression::None => rest.to_string(),
+        Compression::Gzip => format!("{rest}.gz"),
+    };
+    Ok(get_objects_dir().join(fanout).join(name))
+}
+
+/// Opens an object for reading, decompressing it on the fly
+pub fn open_object(hash: &str, compression: Compression) -> io::Result<Box<dyn Read>> {
+    let file = File::open(object_path(hash, compression)?)?;
+    Ok(decompress(file, compression))
+}
+
+fn decompress(file: File, compression: Compression) -> Box<dyn Read> {
+    match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(GzDecoder::new(file)),
+    }
+}
+
+/// Inverse of [`object_path`], turns a path relative to the objects directory
+/// back into the object's hash and compression
+pub fn parse_object_path(path: &Path) -> Option<(String, Compression)> {
+    let mut components = path.iter();
+    let fanout = components.next()?.to_str()?;
+    let name = components.next()?.to_str()?;
+
+    if components.next().is_some() {
+        return None;
+    }
+
+    let (rest, compression) = match name.strip_suffix(".gz") {
+        Some(rest) => (rest, Compression::Gzip),
+        None => (name, Compression::None),
+    };
+
+    let hash = fanout.to_string() + rest;
+    is_valid_hash(&hash).then_some((hash, compression))
+}
+
+pub fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn copy_hashed(src: &mut impl Read, dst: &mut impl Write, hasher: &mut Sha256) -> io::Result<u64> {
+    let mut buf = [0u8; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let read = src.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        dst.write_all(&buf[..read])?;
+        size += read as u64;
+    }
+
+    Ok(size)
+}
+
+pub fn hash_bytes(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+/// Hashes a file the same way [`store_file`] does without storing it
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    copy_hashed(&mut File::open(path)?, &mut io::sink(), &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Copies `path` into the object store and returns its hash, its original
+/// size and the size it takes up in the store. The contents are hashed while
+/// they are copied into a temporary file, so a file that changes mid-snap can
+/// never end up stored under the wrong hash.
+pub fn store_file(path: &Path, compression: Compression) -> io::Result<(String, u64, u64)> {
+    let objects_dir = create_objects_dir()?;
+
+    let tmp_path = objects_dir.join(format!(".tmp-{}", process::id()));
+    let mut src = File::open(path)?;
+    let mut tmp = create_private_file(&tmp_path)?;
+    let mut hasher = Sha256::new();
+
+    let result = match compression {
+        Compression::None => copy_hashed(&mut src, &mut tmp, &mut hasher)
+            .and_then(|size| tmp.sync_all().map(|_| size)),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(tmp, GzLevel::default());
+            copy_hashed(&mut src, &mut encoder, &mut hasher)
+                .and_then(|size| encoder.finish()?.sync_all().map(|_| size))
+        }
+    };
+
+    let size = match result {
+        Ok(size) => size,
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    };
+
+    let hash = to_hex(&hasher.finalize());
+    let dst = object_path(&hash, compression)?;
+
+    if dst.exists() {
+        fs::remove_file(&tmp_path)?;
+    } else {
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&tmp_path, &dst)?;
+    }
+
+    let stored_size = fs::metadata(&dst)?.len();
+    Ok((hash, size, stored_size))
+}
+
+/// Writes an object that is already in its stored form, e.g. one read out of
+/// an exported archive, into `staging_dir` and returns where it was written,
+/// or `None` if the store already has it. Its contents are checked against
+/// `hash` so a tampered archive can't poison the store once the object is
+/// moved in by [`commit_object`].
+pub fn stage_object(
+    staging_dir: &Path,
+    hash: &str,
+    compression: Compression,
+    src: &mut impl Read,
+) -> io::Result<Option<PathBuf>> {
+    if object_path(hash, compression)?.exists() {
+        return Ok(None);
+    }
+
+    let staged = match compression {
+        Compression::None => staging_dir.join(hash),
+        Compression::Gzip => staging_dir.join(format!("{hash}.gz")),
+    };
+
+    let result = (|| {
+        io::copy(src, &mut create_private_file(&staged)?)?;
+
+        let mut hasher = Sha256::new();
+        let mut contents = decompress(File::open(&staged)?, compression);
+        copy_hashed(&mut contents, &mut io::sink(), &mut hasher)?;
+
+        if to_hex(&hasher.finalize()) != hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("contents of object {hash} don't match its hash"),
+            ));
+        }
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    result.map(|_| Some(staged))
+}
+
+/// Moves an object staged by [`stage_object`] into the store
+pub fn commit_object(staged: &Path, hash: &str, compression: Compression) -> io::Result<()> {
+    let dst = object_path(hash, compression)?;
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(staged, dst)
+}
+
+/// Removes every object whose path is not listed in `referenced` and returns
+/// how many objects and bytes were reclaimed. Callers hold the store lock
+/// exclusively.
+pub fn collect_garbage(referenced: &HashSet<PathBuf>) -> io::Result<(usize, u64)> {
+    let objects_dir = get_objects_dir();
+    let mut removed = 0;
+    let mut reclaimed = 0;
+
+    if !objects_dir.exists() {
+        return Ok((removed, reclaimed));
+    }
+
+    for fanout in fs::read_dir(&objects_dir)? {
+        let fanout = fanout?;
+        let fanout_path = fanout.path();
+
+        if !fanout_path.is_dir() {
+            // Leftover temporary files from an interrupted snap
+            let meta = fanout.metadata()?;
+            let age = meta
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+            if age.is_some_and(|age| age > TMP_MAX_AGE) {
+                reclaimed += meta.len();
+                fs::remove_file(&fanout_path)?;
+            }
+            continue;
+        }
+
+        for object in fs::read_dir(&fanout_path)? {
+            let object = object?;
+
+            if !referenced.contains(&object.path()) {
+                reclaimed += object.metadata()?.len();
+                fs::remove_file(object.path())?;
+                removed += 1;
+            }
+        }
+
+        if fs::read_dir(&fanout_path)?.next().is_none() {
+            fs::remove_dir(&fanout_path)?;
+        }
+    }
+
+    Ok((removed, reclaimed))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xab, 0xff]), "000fabff");
+    }
+
+    #[test]
+    fn test_parse_object_path() {
+        let hash = "ab".repeat(32);
+
+        assert_eq!(
+            parse_object_path(&PathBuf::from("ab").join(&hash[2..])),
+            Some((hash.clone(), Compression::None))
+        );
+        assert_eq!(
+            parse_object_path(&PathBuf::from("ab").join(format!("{}.gz", &hash[2..]))),
+            Some((hash.clone(), Compression::Gzip))
+        );
+        assert_eq!(parse_object_path(Path::new("ab/not-a-hash")), None);
+        assert_eq!(parse_object_path(Path::new("../../etc/passwd")), None);
+    }
+
+    #[test]
+    fn test_object_path() {
+        let hash = "ab".repeat(32);
+        let raw = object_path(&hash, Compression::None).unwrap();
+        let gzip = object_path(&hash, Compression::Gzip).unwrap();
+
+        assert!(raw.ends_with(format!("ab/{}", &hash[2..])));
+        assert!(gzip.ends_with(format!("ab/{}.gz", &hash[2..])));
+        assert!(object_path("xx/tmp/home/private", Compression::None).is_err());
+        assert!(object_path(&format!("../{}", &hash[3..]), Compression::None).is_err());
+    }
+}